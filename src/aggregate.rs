@@ -0,0 +1,291 @@
+use api::{AggregateFunction, AggregateValue, GroupKey, GroupedRow};
+use int_blocks::ScalarValue;
+use std::cmp;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+// Accumulates values of a single (column, function) pair, possibly across many partitions
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateState {
+    pub function : AggregateFunction,
+    // Number of values seen (rows where the column is not set are not counted)
+    pub count : u64,
+    // Sum (also for Avg), min or max so far
+    value : Accumulated
+}
+
+// Integers are kept wider than the columns, so their sum can't overflow
+#[derive(Debug, Clone, PartialEq)]
+enum Accumulated {
+    None,
+    UInt(i128),
+    Int(i128),
+    Float(f64)
+}
+
+impl AggregateState {
+    pub fn new(function : &AggregateFunction) -> AggregateState {
+        AggregateState {
+            function: function.to_owned(),
+            count: 0,
+            value: Accumulated::None
+        }
+    }
+
+    pub fn add(&mut self, v : ScalarValue) {
+        self.count += 1;
+
+        self.value = match (v, &self.value) {
+            // Strings can be only counted, other functions are rejected before scanning
+            (ScalarValue::Str(_), _) => return,
+            (ScalarValue::UInt(x), &Accumulated::UInt(prev)) => Accumulated::UInt(self.combine(prev, x as i128)),
+            (ScalarValue::UInt(x), _) => Accumulated::UInt(x as i128),
+            (ScalarValue::Int(x), &Accumulated::Int(prev)) => Accumulated::Int(self.combine(prev, x as i128)),
+            (ScalarValue::Int(x), _) => Accumulated::Int(x as i128),
+            (ScalarValue::Float(x), &Accumulated::Float(prev)) => Accumulated::Float(self.combine_float(prev, x)),
+            (ScalarValue::Float(x), _) => Accumulated::Float(x)
+        };
+    }
+
+    fn combine(&self, prev : i128, x : i128) -> i128 {
+        match self.function {
+            AggregateFunction::Count => prev,
+            AggregateFunction::Sum | AggregateFunction::Avg => prev + x,
+            AggregateFunction::Min => cmp::min(prev, x),
+            AggregateFunction::Max => cmp::max(prev, x)
+        }
+    }
+
+    fn combine_float(&self, prev : f64, x : f64) -> f64 {
+        match self.function {
+            AggregateFunction::Count => prev,
+            AggregateFunction::Sum | AggregateFunction::Avg => prev + x,
+            AggregateFunction::Min => prev.min(x),
+            AggregateFunction::Max => prev.max(x)
+        }
+    }
+
+    // Sum which doesn't fit in 64 bits is returned as Float
+    pub fn result(&self) -> AggregateValue {
+        match (&self.function, &self.value) {
+            (&AggregateFunction::Count, _) => AggregateValue::UInt(self.count),
+            (_, &Accumulated::None) => AggregateValue::Null,
+            (&AggregateFunction::Avg, &Accumulated::UInt(sum)) | (&AggregateFunction::Avg, &Accumulated::Int(sum)) => AggregateValue::Float(sum as f64 / self.count as f64),
+            (&AggregateFunction::Avg, &Accumulated::Float(sum)) => AggregateValue::Float(sum / self.count as f64),
+            (_, &Accumulated::UInt(x)) if x <= u64::max_value() as i128 => AggregateValue::UInt(x as u64),
+            (_, &Accumulated::Int(x)) if x >= i64::min_value() as i128 && x <= i64::max_value() as i128 => AggregateValue::Int(x as i64),
+            (_, &Accumulated::UInt(x)) | (_, &Accumulated::Int(x)) => AggregateValue::Float(x as f64),
+            (_, &Accumulated::Float(x)) => AggregateValue::Float(x)
+        }
+    }
+}
+
+impl GroupKey {
+    pub fn from_scalar(v : ScalarValue) -> GroupKey {
+        match v {
+            ScalarValue::UInt(x) => GroupKey::UInt(x),
+            ScalarValue::Float(x) => GroupKey::Float(x),
+            ScalarValue::Int(x) => GroupKey::Int(x),
+            ScalarValue::Str(x) => GroupKey::Str(x.to_vec())
+        }
+    }
+
+    // Floats are compared by their bits, flipped so that the order is numeric (and NaN is a regular key)
+    fn order_key(&self) -> (u8, u64, &[u8]) {
+        match self {
+            &GroupKey::Null => (0, 0, &[]),
+            &GroupKey::UInt(x) => (1, x, &[]),
+            &GroupKey::Str(ref x) => (2, 0, x.as_slice()),
+            &GroupKey::Float(x) => {
+                let bits = x.to_bits();
+                (3, if bits >> 63 == 1 { !bits } else { bits | 1 << 63 }, &[])
+            },
+            // Flipping the sign bit keeps the order of two's complement values
+            &GroupKey::Int(x) => (4, (x as u64) ^ (1 << 63), &[])
+        }
+    }
+}
+
+impl PartialEq for GroupKey {
+    fn eq(&self, other : &GroupKey) -> bool {
+        self.order_key() == other.order_key()
+    }
+}
+
+impl Eq for GroupKey {}
+
+impl PartialOrd for GroupKey {
+    fn partial_cmp(&self, other : &GroupKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for GroupKey {
+    fn cmp(&self, other : &GroupKey) -> Ordering {
+        self.order_key().cmp(&other.order_key())
+    }
+}
+
+impl Hash for GroupKey {
+    fn hash<H : Hasher>(&self, state : &mut H) {
+        self.order_key().hash(state)
+    }
+}
+
+// Aggregation states for each distinct key seen so far
+pub struct GroupedAggregation {
+    functions : Vec<AggregateFunction>,
+    index : HashMap<Vec<GroupKey>, usize>,
+    pub keys : Vec<Vec<GroupKey>>,
+    pub row_counts : Vec<u64>,
+    pub states : Vec<Vec<AggregateState>>
+}
+
+impl GroupedAggregation {
+    pub fn new(aggregates : &Vec<(u32, AggregateFunction)>) -> GroupedAggregation {
+        GroupedAggregation {
+            functions: aggregates.iter().map(|&(_, ref function)| function.to_owned()).collect(),
+            index: HashMap::new(),
+            keys: Vec::new(),
+            row_counts: Vec::new(),
+            states: Vec::new()
+        }
+    }
+
+    // Counts the row in its group (creating the group when needed) and returns the group index
+    pub fn add_row(&mut self, key : Vec<GroupKey>) -> usize {
+        let group_index = match self.index.get(&key) {
+            Some(group_index) => *group_index,
+            None => {
+                let group_index = self.keys.len();
+                self.index.insert(key.to_owned(), group_index);
+                self.keys.push(key);
+                self.row_counts.push(0);
+                self.states.push(self.functions.iter().map(|function| AggregateState::new(function)).collect());
+                group_index
+            }
+        };
+
+        self.row_counts[group_index] += 1;
+        group_index
+    }
+
+    // Final results, sorted by key
+    pub fn into_rows(self) -> Vec<GroupedRow> {
+        let mut rows:Vec<GroupedRow> = self.keys.into_iter().zip(self.row_counts.into_iter()).zip(self.states.into_iter())
+            .map(|((key, row_count), states)| GroupedRow {
+                key: key,
+                row_count: row_count,
+                values: states.iter().map(|state| state.result()).collect()
+            })
+            .collect();
+
+        rows.sort_by(|a, b| a.key.cmp(&b.key));
+        rows
+    }
+}
+
+
+#[test]
+fn it_aggregates_values() {
+    let values = vec![ScalarValue::UInt(7), ScalarValue::UInt(2), ScalarValue::UInt(9)];
+
+    let mut results = Vec::new();
+    for function in vec![AggregateFunction::Count, AggregateFunction::Sum, AggregateFunction::Min, AggregateFunction::Max, AggregateFunction::Avg] {
+        let mut state = AggregateState::new(&function);
+        for v in &values {
+            state.add(*v);
+        }
+        results.push(state.result());
+    }
+
+    assert_eq!(vec![
+        AggregateValue::UInt(3),
+        AggregateValue::UInt(18),
+        AggregateValue::UInt(2),
+        AggregateValue::UInt(9),
+        AggregateValue::Float(6.0)
+    ], results);
+
+    // Strings are only counted
+    let mut state = AggregateState::new(&AggregateFunction::Max);
+    state.add(ScalarValue::Str("foo".as_bytes()));
+    assert_eq!(AggregateValue::Null, state.result());
+    assert_eq!(AggregateValue::Null, AggregateState::new(&AggregateFunction::Avg).result());
+
+    // Sums don't overflow
+    let mut sum = AggregateState::new(&AggregateFunction::Sum);
+    let mut avg = AggregateState::new(&AggregateFunction::Avg);
+    for _ in 0..2 {
+        sum.add(ScalarValue::UInt(u64::max_value()));
+        avg.add(ScalarValue::UInt(u64::max_value()));
+    }
+    assert_eq!(AggregateValue::Float(2.0 * u64::max_value() as f64), sum.result());
+    assert_eq!(AggregateValue::Float(u64::max_value() as f64), avg.result());
+}
+
+#[test]
+fn it_groups_rows() {
+    let mut grouping = GroupedAggregation::new(&vec![(1, AggregateFunction::Max)]);
+
+    for &(key, v) in &[(3, 10), (1, 20), (3, 30)] {
+        let group_index = grouping.add_row(vec![GroupKey::UInt(key)]);
+        grouping.states[group_index][0].add(ScalarValue::UInt(v));
+    }
+
+    assert_eq!(vec![
+        GroupedRow { key: vec![GroupKey::UInt(1)], row_count: 1, values: vec![AggregateValue::UInt(20)] },
+        GroupedRow { key: vec![GroupKey::UInt(3)], row_count: 2, values: vec![AggregateValue::UInt(30)] }
+    ], grouping.into_rows());
+}
+
+#[test]
+fn it_aggregates_and_groups_floats() {
+    let mut state = AggregateState::new(&AggregateFunction::Avg);
+    for v in &[1.5, 2.5, -1.0] {
+        state.add(ScalarValue::Float(*v));
+    }
+    assert_eq!(AggregateValue::Float(1.0), state.result());
+
+    let mut grouping = GroupedAggregation::new(&vec![(1, AggregateFunction::Min)]);
+    for &(key, v) in &[(0.5, 3.0), (-2.0, 1.0), (0.5, -4.5)] {
+        let group_index = grouping.add_row(vec![GroupKey::Float(key)]);
+        grouping.states[group_index][0].add(ScalarValue::Float(v));
+    }
+
+    assert_eq!(vec![
+        GroupedRow { key: vec![GroupKey::Float(-2.0)], row_count: 1, values: vec![AggregateValue::Float(1.0)] },
+        GroupedRow { key: vec![GroupKey::Float(0.5)], row_count: 2, values: vec![AggregateValue::Float(-4.5)] }
+    ], grouping.into_rows());
+}
+
+#[test]
+fn it_aggregates_signed_values() {
+    let values = vec![ScalarValue::Int(-7), ScalarValue::Int(2), ScalarValue::Int(-1)];
+
+    let mut results = Vec::new();
+    for function in vec![AggregateFunction::Sum, AggregateFunction::Min, AggregateFunction::Max, AggregateFunction::Avg] {
+        let mut state = AggregateState::new(&function);
+        for v in &values {
+            state.add(*v);
+        }
+        results.push(state.result());
+    }
+
+    assert_eq!(vec![
+        AggregateValue::Int(-6),
+        AggregateValue::Int(-7),
+        AggregateValue::Int(2),
+        AggregateValue::Float(-2.0)
+    ], results);
+
+    let mut sum = AggregateState::new(&AggregateFunction::Sum);
+    sum.add(ScalarValue::Int(i64::min_value()));
+    sum.add(ScalarValue::Int(-1));
+    assert_eq!(AggregateValue::Float(i64::min_value() as f64 - 1.0), sum.result());
+
+    let mut keys = vec![GroupKey::Int(3), GroupKey::Int(-2), GroupKey::Int(i64::min_value()), GroupKey::Int(0)];
+    keys.sort();
+    assert_eq!(vec![GroupKey::Int(i64::min_value()), GroupKey::Int(-2), GroupKey::Int(0), GroupKey::Int(3)], keys);
+}
@@ -1,9 +1,17 @@
 use bincode::{serialize, deserialize, Infinite};
-use catalog::{BlockType, Column, PartitionInfo};
+use serde::de::DeserializeOwned;
+use catalog::{BlockType, Catalog, Column, PartitionInfo};
+use partition::StatValue;
 use manager::{Manager, BlockCache};
-use int_blocks::{Block, Int32SparseBlock, Int64DenseBlock, Int64SparseBlock, Scannable, Deletable, Movable, Upsertable};
+use int_blocks::{Block, ScalarValue, StringMatcher, MultiScannable, Int32SparseBlock, Int64DenseBlock, Int64SparseBlock, Scannable, Deletable, Movable, Upsertable};
 use std::time::Instant;
+use std::cmp;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
 use scan::{BlockScanConsumer};
+use aggregate::{AggregateState, GroupedAggregation};
+use error;
+use error::Error;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct InsertMessage {
@@ -13,6 +21,23 @@ pub struct InsertMessage {
     pub blocks : Vec<Block> // This can be done right now only because blocks are so trivial
 }
 
+// Same as InsertMessage, but columns are referred to by their names rather than indices
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NamedInsertMessage {
+    pub row_count : u32,
+    pub col_count : u32,
+    pub col_types : Vec<(String, BlockType)>,
+    pub blocks : Vec<Block>,
+    // When set, columns which do not exist yet are added to the catalog with the given type
+    pub create_missing_columns : bool
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NamedInsertResponse {
+    // Index of each column, in the same order as in the message
+    pub columns : Vec<(String, u32)>
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct PartialInsertMessage {
     pub col_count : u32,
@@ -35,24 +60,208 @@ pub enum ScanComparison {
     Eq,
     GtEq,
     Gt,
-    NotEq
+    NotEq,
+    // Applicable to string columns only
+    Contains,
+    StartsWith,
+    EndsWith,
+    Regex,
+    ContainsIgnoreCase,
+    StartsWithIgnoreCase,
+    EndsWithIgnoreCase,
+    RegexIgnoreCase,
+    // Use ScanFilter.vals (or str_vals)
+    In,
+    // Closed range [low, high]
+    Between,
+    // Range [low, high)
+    BetweenHalfOpen,
+    // Whether the (sparse) column is set, no value is needed
+    Exists,
+    NotExists
+}
+
+impl ScanComparison {
+    pub fn is_string_only(&self) -> bool {
+        match self {
+            &ScanComparison::Contains | &ScanComparison::StartsWith | &ScanComparison::EndsWith | &ScanComparison::Regex => true,
+            &ScanComparison::ContainsIgnoreCase | &ScanComparison::StartsWithIgnoreCase | &ScanComparison::EndsWithIgnoreCase | &ScanComparison::RegexIgnoreCase => true,
+            _ => false
+        }
+    }
+
+    pub fn is_multi_value(&self) -> bool {
+        match self {
+            &ScanComparison::In | &ScanComparison::Between | &ScanComparison::BetweenHalfOpen => true,
+            _ => false
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ScanFilter {
     pub column : u32,
     pub op : ScanComparison,
+    // For float columns these are the f64 bits (f64::to_bits), for signed ones the two's complement (i64 as u64)
     pub val : u64,
-    pub str_val : Vec<u8>
+    pub str_val : Vec<u8>,
+    // Values of In, or the low and high bound of a range - encoded the same way as val and str_val
+    pub vals : Vec<u64>,
+    pub str_vals : Vec<Vec<u8>>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc
+}
+
+// Allows combining filters in ways other than AND, e.g. "source = 1 OR (pattern_id = 5 AND p1 != 'x')".
+// Mind that NOT is a complement of the matched rows, so it includes the rows where sparse column is not set.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FilterExpression {
+    Filter(ScanFilter),
+    And(Vec<FilterExpression>),
+    Or(Vec<FilterExpression>),
+    Not(Box<FilterExpression>)
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct ScanRequest {
+    // Inclusive time range, rows outside of it are never returned
     pub min_ts : u64,
     pub max_ts : u64,
-    pub partition_id : u64,
+    // When not set, all partitions overlapping the time range are scanned and the results are combined
+    pub partition_id : Option<u64>,
     pub projection : Vec<u32>,
-    pub filters : Vec<ScanFilter>
+    pub filters : Vec<ScanFilter>,
+    // Evaluated together (AND) with the filters above
+    pub filter_expression : Option<FilterExpression>,
+    // Number of matching rows skipped (after ordering) and max number of rows returned
+    pub offset : u32,
+    pub limit : Option<u32>,
+    // Column and direction, rows where the column is not set always go last
+    pub order_by : Option<(u32, SortOrder)>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AggregateValue {
+    // E.g. min of a column which is not set in any of the matching rows
+    Null,
+    UInt(u64),
+    Float(f64),
+    Int(i64)
+}
+
+// Rows matched by requests other than ScanRequest, the same way as by ScanRequest.
+// Serialized as if these fields were placed directly in the request.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ScanSelection {
+    pub min_ts : u64,
+    pub max_ts : u64,
+    pub partition_id : Option<u64>,
+    pub filters : Vec<ScanFilter>,
+    pub filter_expression : Option<FilterExpression>
+}
+
+// Same filtering as ScanRequest, but only the aggregates are computed instead of returning the rows
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct AggregateRequest {
+    pub selection : ScanSelection,
+    // Count does not include rows where the column is not set, strings can only be counted
+    pub aggregates : Vec<(u32, AggregateFunction)>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct AggregateResponse {
+    pub row_count : u64,
+    // In the same order as requested aggregates
+    pub values : Vec<AggregateValue>
+}
+
+// Value of a group-by column, ordered with Null first (see aggregate.rs for the ordering)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum GroupKey {
+    Null,
+    UInt(u64),
+    Str(Vec<u8>),
+    Float(f64),
+    Int(i64)
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct GroupByRequest {
+    pub selection : ScanSelection,
+    pub group_by : Vec<u32>,
+    pub aggregates : Vec<(u32, AggregateFunction)>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct GroupedRow {
+    // Values of the group_by columns
+    pub key : Vec<GroupKey>,
+    pub row_count : u64,
+    // In the same order as requested aggregates
+    pub values : Vec<AggregateValue>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct GroupByResponse {
+    // Sorted by key
+    pub groups : Vec<GroupedRow>
+}
+
+// Counts (and optionally aggregates) matching rows in fixed width ts intervals
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct HistogramRequest {
+    pub selection : ScanSelection,
+    // In microseconds (same as ts), buckets are aligned to multiples of it; must be greater than zero
+    pub bucket_width : u64,
+    pub aggregates : Vec<(u32, AggregateFunction)>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct HistogramBucket {
+    pub start_ts : u64,
+    pub row_count : u64,
+    // In the same order as requested aggregates
+    pub values : Vec<AggregateValue>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct HistogramResponse {
+    pub bucket_width : u64,
+    // Sorted by start_ts, buckets without matching rows are skipped
+    pub buckets : Vec<HistogramBucket>
+}
+
+// Opens a cursor over the scan results, which are then returned in chunks of at most chunk_size rows
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct CursorScanRequest {
+    pub scan : ScanRequest,
+    pub chunk_size : u32
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct FetchMoreRequest {
+    pub cursor_id : u64
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct CursorResponse {
+    pub cursor_id : u64,
+    // When false, the cursor is closed and FetchMore should not be called anymore
+    pub has_more : bool,
+    pub chunk : ScanResultMessage
 }
 
 // Typically for log compaction only
@@ -71,7 +280,8 @@ pub struct DataCompactionRequest {
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct RefreshCatalogResponse {
-    pub columns: Vec<Column>,
+    // Dropped columns are skipped, so each one comes with its id
+    pub columns: Vec<(u32, Column)>,
     pub available_partitions: Vec<PartitionInfo>
 }
 
@@ -81,14 +291,50 @@ pub struct AddColumnRequest {
     pub column_type: BlockType
 }
 
+// Data of the column is removed from disk later on, the id is not reused
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct DropColumnRequest {
+    pub column: u32
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct RenameColumnRequest {
+    pub column: u32,
+    pub new_name: String
+}
+
+// Only widening integer types and dictionary encoding strings are supported, sparse column can become dense only when it is set in all rows
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct AlterColumnTypeRequest {
+    pub column: u32,
+    pub data_type: BlockType
+}
+
+// Payload of ApiResponse when the request failed
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ErrorResponse {
+    pub status : u32,
+    pub message : String
+}
+
+// Envelope of every response. When status is 0, payload holds the operation specific response (empty for
+// operations which don't return anything), otherwise it is an ErrorResponse.
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
-pub struct GenericResponse {
-    pub status : u32
+pub struct ApiResponse {
+    pub status : u32,
+    pub payload : Vec<u8>
 }
 
-impl GenericResponse {
-    pub fn create_as_buf(status : u32) -> Vec<u8> {
-        let resp = GenericResponse { status: status };
+impl ApiResponse {
+    pub fn create_as_buf(result : error::Result<Vec<u8>>) -> Vec<u8> {
+        let resp = match result {
+            Ok(payload) => ApiResponse { status: error::STATUS_OK, payload: payload },
+            Err(e) => {
+                let error_resp = ErrorResponse { status: e.status(), message: e.to_string() };
+                ApiResponse { status: e.status(), payload: serialize(&error_resp, Infinite).unwrap() }
+            }
+        };
+
         serialize(&resp, Infinite).unwrap()
     }
 }
@@ -101,7 +347,16 @@ pub enum ApiOperation {
     RefreshCatalog,
     AddColumn,
     Flush,
-    DataCompaction
+    DataCompaction,
+    Aggregate,
+    GroupBy,
+    Histogram,
+    ScanCursor,
+    FetchMore,
+    InsertNamed,
+    DropColumn,
+    RenameColumn,
+    AlterColumnType
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -111,32 +366,65 @@ pub struct ApiMessage {
 }
 
 impl ApiMessage {
-    pub fn extract_scan_request(&self) -> ScanRequest {
-        assert_eq!(self.op_type, ApiOperation::Scan);
+    // Message of some other operation is a bad payload too
+    fn extract_payload<T : DeserializeOwned>(&self, op_type : ApiOperation) -> error::Result<T> {
+        if self.op_type != op_type {
+            return Err(Error::BadPayload(format!("Expected {:?} message, got {:?}", op_type, self.op_type)));
+        }
+
+        Ok(deserialize(&self.payload[..])?)
+    }
+
+    pub fn extract_scan_request(&self) -> error::Result<ScanRequest> {
+        self.extract_payload(ApiOperation::Scan)
+    }
+
+    pub fn extract_insert_message(&self) -> error::Result<InsertMessage> {
+        self.extract_payload(ApiOperation::Insert)
+    }
+
+    pub fn extract_named_insert_message(&self) -> error::Result<NamedInsertMessage> {
+        self.extract_payload(ApiOperation::InsertNamed)
+    }
+
+    pub fn extract_data_compaction_request(&self) -> error::Result<DataCompactionRequest> {
+        self.extract_payload(ApiOperation::DataCompaction)
+    }
+
+    pub fn extract_aggregate_request(&self) -> error::Result<AggregateRequest> {
+        self.extract_payload(ApiOperation::Aggregate)
+    }
+
+    pub fn extract_group_by_request(&self) -> error::Result<GroupByRequest> {
+        self.extract_payload(ApiOperation::GroupBy)
+    }
 
-        let scan_request = deserialize(&self.payload[..]).unwrap();
-        scan_request
+    pub fn extract_histogram_request(&self) -> error::Result<HistogramRequest> {
+        self.extract_payload(ApiOperation::Histogram)
     }
 
-    pub fn extract_insert_message(&self) -> InsertMessage {
-        assert_eq!(self.op_type, ApiOperation::Insert);
+    pub fn extract_cursor_scan_request(&self) -> error::Result<CursorScanRequest> {
+        self.extract_payload(ApiOperation::ScanCursor)
+    }
 
-        let insert_message = deserialize(&self.payload[..]).unwrap();
-        insert_message
+    pub fn extract_fetch_more_request(&self) -> error::Result<FetchMoreRequest> {
+        self.extract_payload(ApiOperation::FetchMore)
     }
 
-    pub fn extract_data_compaction_request(&self) -> DataCompactionRequest {
-        assert_eq!(self.op_type, ApiOperation::DataCompaction);
+    pub fn extract_drop_column_request(&self) -> error::Result<DropColumnRequest> {
+        self.extract_payload(ApiOperation::DropColumn)
+    }
 
-        let compaction_request = deserialize(&self.payload[..]).unwrap();
-        compaction_request
+    pub fn extract_rename_column_request(&self) -> error::Result<RenameColumnRequest> {
+        self.extract_payload(ApiOperation::RenameColumn)
     }
 
-    pub fn extract_add_column_message(&self) -> AddColumnRequest {
-        assert_eq!(self.op_type, ApiOperation::AddColumn);
+    pub fn extract_alter_column_type_request(&self) -> error::Result<AlterColumnTypeRequest> {
+        self.extract_payload(ApiOperation::AlterColumnType)
+    }
 
-        let column_message = deserialize(&self.payload[..]).unwrap();
-        column_message
+    pub fn extract_add_column_message(&self) -> error::Result<AddColumnRequest> {
+        self.extract_payload(ApiOperation::AddColumn)
     }
 }
 
@@ -149,21 +437,217 @@ impl ScanResultMessage {
             blocks: Vec::new()
         }
     }
+
+    // No rows, but still describing the projected columns (so the client does not need to special-case it)
+    pub fn empty_projection(catalog : &Catalog, projection : &Vec<u32>) -> ScanResultMessage {
+        let mut msg = ScanResultMessage::new();
+        msg.col_count = projection.len() as u32;
+
+        for col_index in projection {
+            let data_type = &catalog.columns[*col_index as usize].data_type;
+            msg.col_types.push((*col_index, data_type.to_owned()));
+            msg.blocks.push(Block::create_block(data_type));
+        }
+
+        msg
+    }
+
+    // Puts rows of the other message after the rows of this one (both need to have the same projection)
+    pub fn append(&mut self, other : &ScanResultMessage) {
+        assert_eq!(self.col_types, other.col_types);
+
+        for (block, other_block) in self.blocks.iter_mut().zip(other.blocks.iter()) {
+            block.append_block(other_block, self.row_count);
+        }
+
+        self.row_count += other.row_count;
+    }
+}
+
+// Values not used by the comparison are left empty
+impl ScanFilter {
+    pub fn new(column : u32, op : ScanComparison, val : u64) -> ScanFilter {
+        ScanFilter { column: column, op: op, val: val, str_val: vec![], vals: vec![], str_vals: vec![] }
+    }
+
+    pub fn new_str(column : u32, op : ScanComparison, str_val : &[u8]) -> ScanFilter {
+        ScanFilter { str_val: str_val.to_vec(), ..ScanFilter::new(column, op, 0) }
+    }
+
+    pub fn new_multi(column : u32, op : ScanComparison, vals : Vec<u64>) -> ScanFilter {
+        ScanFilter { vals: vals, ..ScanFilter::new(column, op, 0) }
+    }
+
+    pub fn new_str_multi(column : u32, op : ScanComparison, str_vals : Vec<Vec<u8>>) -> ScanFilter {
+        ScanFilter { str_vals: str_vals, ..ScanFilter::new(column, op, 0) }
+    }
+}
+
+// Scans all rows of all partitions, unless narrowed down with the setters below
+impl ScanRequest {
+    pub fn new(projection : Vec<u32>) -> ScanRequest {
+        ScanRequest {
+            min_ts: 0,
+            max_ts: u64::max_value(),
+            partition_id: None,
+            projection: projection,
+            filters: vec![],
+            filter_expression: None,
+            offset: 0,
+            limit: None,
+            order_by: None
+        }
+    }
+
+    pub fn time_range(mut self, min_ts : u64, max_ts : u64) -> ScanRequest {
+        self.min_ts = min_ts;
+        self.max_ts = max_ts;
+        self
+    }
+
+    pub fn partition(mut self, partition_id : u64) -> ScanRequest {
+        self.partition_id = Some(partition_id);
+        self
+    }
+
+    pub fn filters(mut self, filters : Vec<ScanFilter>) -> ScanRequest {
+        self.filters = filters;
+        self
+    }
+
+    pub fn filter_expression(mut self, expr : FilterExpression) -> ScanRequest {
+        self.filter_expression = Some(expr);
+        self
+    }
+
+    pub fn offset(mut self, offset : u32) -> ScanRequest {
+        self.offset = offset;
+        self
+    }
+
+    pub fn limit(mut self, limit : u32) -> ScanRequest {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn order_by(mut self, column : u32, order : SortOrder) -> ScanRequest {
+        self.order_by = Some((column, order));
+        self
+    }
+}
+
+impl ScanSelection {
+    pub fn to_scan_request(&self) -> ScanRequest {
+        ScanRequest {
+            min_ts: self.min_ts,
+            max_ts: self.max_ts,
+            partition_id: self.partition_id,
+            filters: self.filters.to_owned(),
+            filter_expression: self.filter_expression.to_owned(),
+            offset: 0,
+            limit: None,
+            order_by: None,
+            projection: vec![]
+        }
+    }
 }
 
 impl RefreshCatalogResponse {
     pub fn new(manager: &Manager) -> RefreshCatalogResponse {
         RefreshCatalogResponse {
-            columns: manager.catalog.columns.to_owned(),
+            columns: manager.catalog.live_columns(),
             available_partitions: manager.catalog.available_partitions.to_owned()
         }
     }
 }
 
+fn check_column(catalog : &Catalog, col_index : u32) -> error::Result<&Column> {
+    catalog.column(col_index).ok_or(Error::UnknownColumn(col_index))
+}
+
+fn check_filter(catalog : &Catalog, filter : &ScanFilter) -> error::Result<()> {
+    let column = check_column(catalog, filter.column)?;
+
+    if column.data_type.is_string() && String::from_utf8(filter.str_val.to_owned()).is_err() {
+        return Err(Error::BadPayload(format!("Filter value for column {} is not valid UTF-8", filter.column)));
+    }
+
+    if filter.op.is_string_only() {
+        if !column.data_type.is_string() {
+            return Err(Error::TypeMismatch(format!("{:?} can't be applied to {:?} column {}", filter.op, column.data_type, filter.column)));
+        }
+
+        if let Err(e) = StringMatcher::new(&filter.op, &filter.str_val) {
+            return Err(Error::BadPayload(format!("Invalid pattern for column {}: {}", filter.column, e)));
+        }
+    }
+
+    let vals_count = if column.data_type.is_string() { filter.str_vals.len() } else { filter.vals.len() };
+    if (filter.op == ScanComparison::Between || filter.op == ScanComparison::BetweenHalfOpen) && vals_count != 2 {
+        return Err(Error::BadPayload(format!("Range filter for column {} needs exactly 2 values, got {}", filter.column, vals_count)));
+    }
+
+    Ok(())
+}
+
+fn check_expression(catalog : &Catalog, expr : &FilterExpression) -> error::Result<()> {
+    match expr {
+        &FilterExpression::Filter(ref filter) => check_filter(catalog, filter),
+        &FilterExpression::And(ref exprs) | &FilterExpression::Or(ref exprs) => {
+            for e in exprs {
+                check_expression(catalog, e)?;
+            }
+            Ok(())
+        },
+        &FilterExpression::Not(ref e) => check_expression(catalog, e)
+    }
+}
+
+// Everything referenced by the request must exist, so the scan itself can't fail half way
+// Columns might be dropped while a cursor is open, so it checks them again on each fetch
+pub fn check_projection(catalog : &Catalog, projection : &Vec<u32>) -> error::Result<()> {
+    for col_index in projection {
+        check_column(catalog, *col_index)?;
+    }
+
+    Ok(())
+}
+
+fn check_scan_request(catalog : &Catalog, req : &ScanRequest) -> error::Result<()> {
+    check_projection(catalog, &req.projection)?;
+
+    for filter in &req.filters {
+        check_filter(catalog, filter)?;
+    }
+
+    if let Some(ref expr) = req.filter_expression {
+        check_expression(catalog, expr)?;
+    }
+
+    if let Some((order_col, _)) = req.order_by {
+        check_column(catalog, order_col)?;
+    }
+
+    Ok(())
+}
+
+fn check_aggregates(catalog : &Catalog, aggregates : &Vec<(u32, AggregateFunction)>) -> error::Result<()> {
+    for &(col_index, ref function) in aggregates {
+        let data_type = &check_column(catalog, col_index)?.data_type;
+
+        // Values of strings and flags can be only counted
+        if *function != AggregateFunction::Count && (data_type.is_string() || *data_type == BlockType::Bitmap) {
+            return Err(Error::TypeMismatch(format!("{:?} of {:?} column {} is not supported", function, data_type, col_index)));
+        }
+    }
+
+    Ok(())
+}
+
 // FIXME: this is ugly copypasta
 
-fn consume_empty_filter<'a>(manager : &Manager, cache : &'a mut BlockCache, consumer : &mut BlockScanConsumer) {
-    let scanned_block = manager.load_block(&cache.partition_info, 0); // ts
+fn consume_empty_filter<'a>(manager : &Manager, cache : &'a mut BlockCache, consumer : &mut BlockScanConsumer) -> error::Result<()> {
+    let scanned_block = manager.load_block(&cache.partition_info, 0)?; // ts
 
     match &scanned_block {
         &Block::Int64Dense(ref x) => {
@@ -175,22 +659,172 @@ fn consume_empty_filter<'a>(manager : &Manager, cache : &'a mut BlockCache, cons
     }
 
     cache.cache_block(scanned_block, 0);
+    Ok(())
+}
+
+fn consume_ts_range<'a>(manager : &Manager, cache : &'a mut BlockCache, min_ts : u64, max_ts : u64, consumer : &mut BlockScanConsumer) -> error::Result<()> {
+    let scanned_block = manager.load_block(&cache.partition_info, 0)?; // ts
+
+    match &scanned_block {
+        &Block::Int64Dense(ref x) => {
+            for (i, ts) in x.data.iter().enumerate() {
+                if *ts >= min_ts && *ts <= max_ts {
+                    consumer.matching_offsets.push(i as u32);
+                }
+            }
+        },
+        _ => println!("This is unexpected - TS is not here")
+    }
+
+    cache.cache_block(scanned_block, 0);
+    Ok(())
 }
 
-fn consume_filters<'a>(manager : &'a Manager, cache: &'a mut BlockCache, filter: &'a ScanFilter, mut consumer: &mut BlockScanConsumer) {
-    let scanned_block = manager.load_block(&cache.partition_info, filter.column); // ts
+// What the zone map tells about the rows matching a filter
+enum ZoneMatch {
+    NoRows,
+    AllRows(u32),
+    Unknown
+}
+
+// Whether any value within the bounds can match, and whether all of them do (None if the bounds don't tell)
+fn bounds_match<T : PartialOrd>(min : &T, max : &T, op : &ScanComparison, val : &T, vals : &Vec<T>) -> Option<(bool, bool)> {
+    let matches = match op {
+        &ScanComparison::Lt => (min < val, max < val),
+        &ScanComparison::LtEq => (min <= val, max <= val),
+        &ScanComparison::Eq => (min <= val && val <= max, min == max && min == val),
+        &ScanComparison::GtEq => (max >= val, min >= val),
+        &ScanComparison::Gt => (max > val, min > val),
+        &ScanComparison::NotEq => (!(min == max && min == val), val < min || val > max),
+        &ScanComparison::In => (vals.iter().any(|v| min <= v && v <= max), min == max && vals.iter().any(|v| v == min)),
+        &ScanComparison::Between if vals.len() == 2 => (vals[0] <= *max && vals[1] >= *min, vals[0] <= *min && *max <= vals[1]),
+        &ScanComparison::BetweenHalfOpen if vals.len() == 2 => (vals[0] <= *max && vals[1] > *min, vals[0] <= *min && *max < vals[1]),
+        _ => return None
+    };
+
+    Some(matches)
+}
+
+fn zone_match(part_info : &PartitionInfo, data_type : &BlockType, filter : &ScanFilter) -> ZoneMatch {
+    let row_count = match part_info.row_count() {
+        Some(row_count) => row_count,
+        None => return ZoneMatch::Unknown
+    };
+
+    // Empty bitmap still has a value (false) in each row
+    if *data_type == BlockType::Bitmap {
+        return ZoneMatch::Unknown;
+    }
+
+    let stats = match part_info.block_stats(filter.column) {
+        Some(stats) => stats,
+        // Dense column added after the partition was stored is zero in every row, rather than null
+        None if data_type.is_dense() => return ZoneMatch::Unknown,
+        // Block is empty, so every row is null
+        None => return if filter.op == ScanComparison::NotExists { ZoneMatch::AllRows(row_count) } else { ZoneMatch::NoRows }
+    };
+
+    let fully_populated = stats.non_null_count == row_count;
+
+    let matches = match (&filter.op, &stats.bounds) {
+        (&ScanComparison::Exists, _) => Some((true, true)),
+        (&ScanComparison::NotExists, _) => return if fully_populated { ZoneMatch::NoRows } else { ZoneMatch::Unknown },
+        (_, &None) => None,
+        (_, &Some((StatValue::UInt(min), StatValue::UInt(max)))) => bounds_match(&min, &max, &filter.op, &filter.val, &filter.vals),
+        (_, &Some((StatValue::Int(min), StatValue::Int(max)))) => {
+            bounds_match(&min, &max, &filter.op, &(filter.val as i64), &filter.vals.iter().map(|v| *v as i64).collect())
+        },
+        (_, &Some((StatValue::Float(min), StatValue::Float(max)))) => {
+            // Scans of f32 blocks compare with the value converted to f32
+            let to_float = |v : u64| if *data_type == BlockType::Float32Sparse { f64::from_bits(v) as f32 as f64 } else { f64::from_bits(v) };
+            bounds_match(&min, &max, &filter.op, &to_float(filter.val), &filter.vals.iter().map(|v| to_float(*v)).collect())
+        },
+        (_, &Some((StatValue::Str(ref min), StatValue::Str(ref max)))) => bounds_match(min, max, &filter.op, &filter.str_val, &filter.str_vals),
+        _ => None
+    };
+
+    match matches {
+        Some((false, _)) => ZoneMatch::NoRows,
+        // Null rows never match
+        Some((true, true)) if fully_populated => ZoneMatch::AllRows(row_count),
+        _ => ZoneMatch::Unknown
+    }
+}
+
+fn consume_filters<'a>(manager : &'a Manager, cache: &'a mut BlockCache, filter: &'a ScanFilter, mut consumer: &mut BlockScanConsumer) -> error::Result<()> {
+    // The block doesn't have to be loaded at all if its zone map tells
+    match zone_match(&cache.partition_info, &manager.catalog.columns[filter.column as usize].data_type, filter) {
+        ZoneMatch::NoRows => return Ok(()),
+        ZoneMatch::AllRows(row_count) => {
+            consumer.matching_offsets.extend(0..row_count);
+            return Ok(());
+        },
+        ZoneMatch::Unknown => ()
+    }
+
+    let scanned_block = manager.load_block(&cache.partition_info, filter.column)?; // ts
     // String or Int?
     //manager.catalog.columns[filter.column]
 
+    let op = filter.op.clone();
+    let multi_value = op.is_multi_value();
+
     match &scanned_block {
-        &Block::StringBlock(ref x) => {
-            let str_value:String = String::from_utf8(filter.str_val.to_owned()).unwrap();
-            scanned_block.scan(filter.op.clone(), &str_value, &mut consumer)
+        // Missing offsets are null
+        _ if op == ScanComparison::Exists || op == ScanComparison::NotExists => {
+            let row_count = partition_row_count(manager, cache)?;
+            let present = BlockScanConsumer { matching_offsets: scanned_block.present_offsets(row_count) };
+
+            if op == ScanComparison::Exists {
+                consumer.matching_offsets.extend(present.matching_offsets);
+            } else {
+                consumer.matching_offsets.extend(present.complement(row_count).matching_offsets);
+            }
+        },
+        &Block::StringBlock(_) | &Block::DictString(_) => {
+            if multi_value {
+                scanned_block.scan_multi(op, &filter.str_vals, &mut consumer)
+            } else {
+                let str_value:String = String::from_utf8(filter.str_val.to_owned()).unwrap();
+                scanned_block.scan(op, &str_value, &mut consumer)
+            }
+        },
+        // Float filter values are passed as f64 bits
+        &Block::Float64Dense(_) | &Block::Float64Sparse(_) | &Block::Float32Sparse(_) => {
+            if multi_value {
+                scanned_block.scan_multi(op, &filter.vals.iter().map(|v| f64::from_bits(*v)).collect(), &mut consumer)
+            } else {
+                scanned_block.scan(op, &f64::from_bits(filter.val), &mut consumer)
+            }
+        },
+        // Any non-zero value stands for true
+        &Block::Bitmap(ref b) => {
+            let row_count = partition_row_count(manager, cache)?;
+            if multi_value {
+                b.scan_multi(op, &filter.vals.iter().map(|v| *v != 0).collect(), row_count, &mut consumer)
+            } else {
+                b.scan(op, filter.val != 0, row_count, &mut consumer)
+            }
+        },
+        // Signed filter values are passed as two's complement
+        &Block::SignedInt64Sparse(_) | &Block::SignedInt32Sparse(_) | &Block::SignedInt16Sparse(_) | &Block::SignedInt8Sparse(_) => {
+            if multi_value {
+                scanned_block.scan_multi(op, &filter.vals.iter().map(|v| *v as i64).collect(), &mut consumer)
+            } else {
+                scanned_block.scan(op, &(filter.val as i64), &mut consumer)
+            }
         },
-        _ => scanned_block.scan(filter.op.clone(), &filter.val, &mut consumer)
+        _ => {
+            if multi_value {
+                scanned_block.scan_multi(op, &filter.vals, &mut consumer)
+            } else {
+                scanned_block.scan(op, &filter.val, &mut consumer)
+            }
+        }
     }
 
     cache.cache_block(scanned_block, filter.column);
+    Ok(())
 
     // FIXME: why following doesn't work and we need to use the above way?
 
@@ -207,114 +841,623 @@ fn consume_filters<'a>(manager : &'a Manager, cache: &'a mut BlockCache, filter:
 //    };
 }
 
-fn part_scan_and_combine(manager: &Manager, part_info : &PartitionInfo, mut cache : &mut BlockCache, req : &ScanRequest) -> BlockScanConsumer {
-    let mut consumers:Vec<BlockScanConsumer> = Vec::new();
+// Stored or in memory partition with given id
+pub fn scanned_partition_info(manager: &Manager, partition_id : u64) -> error::Result<PartitionInfo> {
+    match manager.in_mem_partition_info() {
+        Some(part_info) if part_info.id == partition_id => Ok(part_info),
+        _ => manager.find_partition_info(partition_id)
+    }
+}
 
-    if req.filters.is_empty() {
-        let mut consumer = BlockScanConsumer{matching_offsets : Vec::new()};
-        consume_empty_filter(manager, &mut cache, &mut consumer);
-        consumers.push(consumer);
-    } else {
-        for filter in &req.filters {
-            let mut consumer = BlockScanConsumer{matching_offsets : Vec::new()};
-            consume_filters(manager, &mut cache, &filter, &mut consumer);
-            consumers.push(consumer);
+// The in memory partition (if not empty) goes last, as it holds the most recent data
+fn scanned_partitions(manager: &Manager, partition_id : Option<u64>, min_ts : u64, max_ts : u64) -> error::Result<Vec<PartitionInfo>> {
+    let candidates = match partition_id {
+        Some(id) => vec![scanned_partition_info(manager, id)?],
+        None => {
+            let in_mem_part_info = manager.in_mem_partition_info();
+            let mut part_infos = manager.catalog.available_partitions.to_owned();
+            part_infos.extend(in_mem_part_info);
+            part_infos
         }
-    }
+    };
 
-    BlockScanConsumer::merge_and_scans(&consumers)
+    Ok(candidates.into_iter().filter(|part_info| part_info.overlaps(min_ts, max_ts)).collect())
 }
 
-pub fn handle_data_compaction(manager: &Manager, req : &DataCompactionRequest) {
-    let part_info = &manager.find_partition_info(req.partition_id);
-    let mut cache = BlockCache::new(part_info);
+fn partition_row_count(manager : &Manager, cache : &mut BlockCache) -> error::Result<u32> {
+    Ok(cache.get_cached_or_load(manager, 0)?.len() as u32)
+}
 
-    let scan_req = ScanRequest {
-        min_ts: 0,
-        max_ts: u64::max_value(),
-        partition_id: req.partition_id,
-        filters: req.filters.to_owned(),
-        projection: vec![]
-    };
+fn consume_expression(manager : &Manager, mut cache : &mut BlockCache, expr : &FilterExpression) -> error::Result<BlockScanConsumer> {
+    match expr {
+        &FilterExpression::Filter(ref filter) => {
+            let mut consumer = BlockScanConsumer::new();
+            consume_filters(manager, &mut cache, filter, &mut consumer)?;
+            Ok(consumer)
+        },
+        &FilterExpression::And(ref exprs) => {
+            if exprs.is_empty() {
+                let mut consumer = BlockScanConsumer::new();
+                consume_empty_filter(manager, &mut cache, &mut consumer)?;
+                return Ok(consumer);
+            }
 
-    let combined_consumer = part_scan_and_combine(manager, part_info, &mut cache, &scan_req);
+            let consumers = exprs.iter().map(|e| consume_expression(manager, &mut cache, e)).collect::<error::Result<_>>()?;
+            Ok(BlockScanConsumer::merge_and_scans(&consumers))
+        },
+        &FilterExpression::Or(ref exprs) => {
+            let consumers = exprs.iter().map(|e| consume_expression(manager, &mut cache, e)).collect::<error::Result<_>>()?;
+            Ok(BlockScanConsumer::merge_or_scans(&consumers))
+        },
+        &FilterExpression::Not(ref e) => {
+            let consumer = consume_expression(manager, &mut cache, e)?;
+            Ok(consumer.complement(partition_row_count(manager, &mut cache)?))
+        }
+    }
+}
 
-    // We have the list of offsets now, lets modify blocks now
+fn part_scan_and_combine(manager: &Manager, part_info : &PartitionInfo, mut cache : &mut BlockCache, req : &ScanRequest) -> error::Result<BlockScanConsumer> {
+    let mut consumers:Vec<BlockScanConsumer> = Vec::new();
 
-    // 1. removed blocks
-    for col in &req.dropped_columns {
-        let mut cur = manager.load_block(part_info, *col);
-        cur.delete(&combined_consumer.matching_offsets);
-        manager.save_block(part_info, &cur, *col);
+    // Partitions fully within the requested range do not need to look at the ts at all
+    if !part_info.is_within(req.min_ts, req.max_ts) {
+        let mut consumer = BlockScanConsumer{matching_offsets : Vec::new()};
+        consume_ts_range(manager, &mut cache, req.min_ts, req.max_ts, &mut consumer)?;
+        consumers.push(consumer);
+    }
+
+    for filter in &req.filters {
+        let mut consumer = BlockScanConsumer{matching_offsets : Vec::new()};
+        consume_filters(manager, &mut cache, &filter, &mut consumer)?;
+        consumers.push(consumer);
+    }
+
+    if let Some(ref expr) = req.filter_expression {
+        consumers.push(consume_expression(manager, &mut cache, expr)?);
+    }
+
+    if consumers.is_empty() {
+        let mut consumer = BlockScanConsumer{matching_offsets : Vec::new()};
+        consume_empty_filter(manager, &mut cache, &mut consumer)?;
+        consumers.push(consumer);
+    }
+
+    Ok(BlockScanConsumer::merge_and_scans(&consumers))
+}
+
+// Dense columns have a value in every row, so compaction can't remove or move their values
+fn check_sparse_column(catalog : &Catalog, col_index : u32) -> error::Result<&Column> {
+    let column = check_column(catalog, col_index)?;
+    if column.data_type.is_dense() {
+        return Err(Error::TypeMismatch(format!("Values of {:?} column {} can't be compacted", column.data_type, col_index)));
+    }
+
+    Ok(column)
+}
+
+fn check_data_compaction_request(catalog : &Catalog, req : &DataCompactionRequest) -> error::Result<()> {
+    for filter in &req.filters {
+        check_filter(catalog, filter)?;
+    }
+
+    for col in &req.dropped_columns {
+        check_sparse_column(catalog, *col)?;
+    }
+
+    for col_pair in &req.renamed_columns {
+        let source_type = &check_sparse_column(catalog, col_pair.0)?.data_type;
+        let target_type = &check_sparse_column(catalog, col_pair.1)?.data_type;
+
+        if source_type != target_type {
+            return Err(Error::TypeMismatch(format!("Cannot move {:?} column {} to {:?} column {}", source_type, col_pair.0, target_type, col_pair.1)));
+        }
+    }
+
+    let upserted = &req.upserted_data;
+    if upserted.col_types.len() < upserted.col_count as usize || upserted.blocks.len() < upserted.col_count as usize {
+        return Err(Error::BadPayload(format!("Expected {} upserted columns, got {} types and {} blocks", upserted.col_count, upserted.col_types.len(), upserted.blocks.len())));
+    }
+
+    for col_no in 0..upserted.col_count as usize {
+        let catalog_col_no = upserted.col_types[col_no].0;
+        let column = check_sparse_column(catalog, catalog_col_no)?;
+
+        // The input block actually contains just a single value that will be multi-upserted
+        let input_block = &upserted.blocks[col_no];
+
+        // Except for bitmaps, which are either set (true) or empty (false) at offset 0
+        let single_value = match input_block {
+            &Block::Bitmap(ref b) => b.data.iter().all(|offset| offset == 0),
+            &Block::DictString(ref b) => b.codes.data.len() == 1 && (b.codes.data[0].1 as usize) < b.dict.len(),
+            _ => input_block.len() == 1
+        };
+
+        if !single_value {
+            return Err(Error::BadPayload(String::from("The upsert block can have only one record which is copied across all matching entries")));
+        }
+
+        if input_block.data_type() != column.data_type {
+            return Err(Error::TypeMismatch(format!("Cannot upsert {:?} block into {:?} column {}", input_block.data_type(), column.data_type, catalog_col_no)));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn handle_data_compaction(manager: &mut Manager, req : &DataCompactionRequest) -> error::Result<()> {
+    check_data_compaction_request(&manager.catalog, req)?;
+
+    let part_info = &manager.find_partition_info(req.partition_id)?;
+    let mut cache = BlockCache::new(part_info);
+
+    let scan_req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: Some(req.partition_id),
+        filters: req.filters.to_owned(),
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![]
+    };
+
+    let combined_consumer = part_scan_and_combine(manager, part_info, &mut cache, &scan_req)?;
+
+    // We have the list of offsets now, lets modify blocks now
+
+    // 1. removed blocks
+    for col in &req.dropped_columns {
+        let mut cur = manager.load_block(part_info, *col)?;
+        cur.delete(&combined_consumer.matching_offsets);
+        manager.save_block(part_info, &cur, *col)?;
+        manager.update_block_stats(part_info.id, *col, &cur)?;
     }
 
     // 2. moved blocks
     for col_pair in &req.renamed_columns {
-        let mut c0 = manager.load_block(part_info, col_pair.0);
-        let mut c1 = manager.load_block(part_info, col_pair.1);
+        let mut c0 = manager.load_block(part_info, col_pair.0)?;
+        let mut c1 = manager.load_block(part_info, col_pair.1)?;
 
         c0.move_data(&mut c1, &combined_consumer);
-        manager.save_block(part_info, &c0, col_pair.0);
-        manager.save_block(part_info, &c1, col_pair.1);
+        manager.save_block(part_info, &c0, col_pair.0)?;
+        manager.save_block(part_info, &c1, col_pair.1)?;
+        manager.update_block_stats(part_info.id, col_pair.0, &c0)?;
+        manager.update_block_stats(part_info.id, col_pair.1, &c1)?;
     }
 
     // 3. upserted blocks
     for col_no in 0..req.upserted_data.col_count {
         let catalog_col_no = req.upserted_data.col_types[col_no as usize].0;
-        let mut block = manager.load_block(part_info, catalog_col_no);
+        let mut block = manager.load_block(part_info, catalog_col_no)?;
 
-        // The input block actually contains just a single value that will be multi-upserted
+        // Already checked to be a matching block with a single value
         let input_block = &req.upserted_data.blocks[col_no as usize];
 
-        if input_block.len() != 1 {
-            panic!("The upsert block can have only one record which is copied across all matching entries");
-        }
-
         match &mut block {
             &mut Block::StringBlock(ref mut b) => match input_block {
                 &Block::StringBlock(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.str_data.as_slice()),
-                _ => panic!("Non matching block types")
+                _ => unreachable!()
+            },
+            &mut Block::DictString(ref mut b) => match input_block {
+                &Block::DictString(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.value(c.codes.data[0].1)),
+                _ => unreachable!()
             },
             &mut Block::Int64Sparse(ref mut b) => match input_block {
                 &Block::Int64Sparse(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data[0].1),
-                _ => panic!("Non matching block types")
+                _ => unreachable!()
             },
             &mut Block::Int32Sparse(ref mut b) => match input_block {
                 &Block::Int32Sparse(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data[0].1),
-                _ => panic!("Non matching block types")
+                _ => unreachable!()
             },
             &mut Block::Int16Sparse(ref mut b) => match input_block {
                 &Block::Int16Sparse(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data[0].1),
-                _ => panic!("Non matching block types")
+                _ => unreachable!()
             },
             &mut Block::Int8Sparse(ref mut b) => match input_block {
                 &Block::Int8Sparse(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data[0].1),
-                _ => panic!("Non matching block types")
+                _ => unreachable!()
+            },
+            &mut Block::Float64Sparse(ref mut b) => match input_block {
+                &Block::Float64Sparse(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data[0].1),
+                _ => unreachable!()
+            },
+            &mut Block::Float32Sparse(ref mut b) => match input_block {
+                &Block::Float32Sparse(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data[0].1),
+                _ => unreachable!()
             },
-            _ => panic!("Not supported block type")
+            &mut Block::SignedInt64Sparse(ref mut b) => match input_block {
+                &Block::SignedInt64Sparse(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data[0].1),
+                _ => unreachable!()
+            },
+            &mut Block::SignedInt32Sparse(ref mut b) => match input_block {
+                &Block::SignedInt32Sparse(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data[0].1),
+                _ => unreachable!()
+            },
+            &mut Block::SignedInt16Sparse(ref mut b) => match input_block {
+                &Block::SignedInt16Sparse(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data[0].1),
+                _ => unreachable!()
+            },
+            &mut Block::SignedInt8Sparse(ref mut b) => match input_block {
+                &Block::SignedInt8Sparse(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data[0].1),
+                _ => unreachable!()
+            },
+            &mut Block::Bitmap(ref mut b) => match input_block {
+                &Block::Bitmap(ref c) => b.multi_upsert(&combined_consumer.matching_offsets, c.data.contains(0)),
+                _ => unreachable!()
+            },
+            _ => unreachable!()
+        }
+
+        manager.save_block(part_info, &block, catalog_col_no)?;
+        manager.update_block_stats(part_info.id, catalog_col_no, &block)?;
+    }
+
+    // Zone maps of the modified blocks
+    manager.store_catalog()
+}
+
+fn compare_sort_keys(a : &GroupKey, b : &GroupKey, order : &SortOrder) -> Ordering {
+    match (a, b) {
+        (&GroupKey::Null, &GroupKey::Null) => Ordering::Equal,
+        (&GroupKey::Null, _) => Ordering::Greater,
+        (_, &GroupKey::Null) => Ordering::Less,
+        _ => match order {
+            &SortOrder::Asc => a.cmp(b),
+            &SortOrder::Desc => b.cmp(a)
         }
+    }
+}
+
+// Materializes given (partition index, offset) rows, keeping their order
+pub fn materialize_rows(manager: &Manager, part_infos : &Vec<PartitionInfo>, rows : &[(usize, u32)], projection : &Vec<u32>) -> error::Result<ScanResultMessage> {
+    // Each partition is materialized in one go, so its blocks are loaded only once
+    let mut part_offsets:Vec<Vec<u32>> = vec![Vec::new(); part_infos.len()];
+    for &(part_no, offset) in rows {
+        part_offsets[part_no].push(offset);
+    }
+
+    let mut scan_msg = ScanResultMessage::empty_projection(&manager.catalog, projection);
+    let mut part_first_rows:Vec<u32> = vec![0; part_infos.len()];
+
+    for (part_no, offsets) in part_offsets.iter_mut().enumerate() {
+        if offsets.is_empty() {
+            continue;
+        }
+
+        offsets.sort();
+        part_first_rows[part_no] = scan_msg.row_count;
+
+        let mut cache = BlockCache::new(&part_infos[part_no]);
+        let consumer = BlockScanConsumer { matching_offsets: offsets.to_owned() };
+
+        let mut part_msg = ScanResultMessage::new();
+        consumer.materialize(&manager, &mut cache, projection, &mut part_msg)?;
+        scan_msg.append(&part_msg);
+    }
+
+    // Now the rows are grouped by partition, so put them back in the requested order
+    let reordering = BlockScanConsumer {
+        matching_offsets: rows.iter().map(|&(part_no, offset)| {
+            part_first_rows[part_no] + part_offsets[part_no].binary_search(&offset).unwrap() as u32
+        }).collect()
+    };
+
+    if !reordering.is_ascending() {
+        scan_msg.blocks = scan_msg.blocks.iter().map(|block| block.consume(&reordering)).collect();
+    }
+
+    Ok(scan_msg)
+}
+
+// Row picked by an ordered scan; the greatest one sorts last, so a max-heap keeps the worst on top
+struct SortCandidate<'a> {
+    key : GroupKey,
+    // Rows having the same key stay in the order they were scanned
+    seq : usize,
+    part_no : usize,
+    offset : u32,
+    order : &'a SortOrder
+}
+
+impl<'a> Ord for SortCandidate<'a> {
+    fn cmp(&self, other: &SortCandidate<'a>) -> Ordering {
+        compare_sort_keys(&self.key, &other.key, self.order).then(self.seq.cmp(&other.seq))
+    }
+}
+
+impl<'a> PartialOrd for SortCandidate<'a> {
+    fn partial_cmp(&self, other: &SortCandidate<'a>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-        manager.save_block(part_info, &block, catalog_col_no);
+impl<'a> PartialEq for SortCandidate<'a> {
+    fn eq(&self, other: &SortCandidate<'a>) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
-pub fn part_scan_and_materialize(manager: &Manager, req : &ScanRequest) -> ScanResultMessage {
+impl<'a> Eq for SortCandidate<'a> {}
+
+// Picks the rows to return according to req.order_by, offset and limit
+fn scan_ordered_rows(manager: &Manager, part_infos : &mut Vec<PartitionInfo>, req : &ScanRequest, order_col : u32, order : &SortOrder) -> error::Result<Vec<(usize, u32)>> {
+    let wanted_count = req.limit.map(|limit| req.offset as usize + limit as usize);
+
+    // When ordering by ts, partitions which can't have better rows than the ones already found can be skipped
+    let is_ts_order = order_col == 0;
+    if is_ts_order {
+        match order {
+            &SortOrder::Asc => part_infos.sort_by_key(|part_info| part_info.min_ts),
+            &SortOrder::Desc => part_infos.sort_by(|a, b| b.max_ts.cmp(&a.max_ts))
+        }
+    }
+
+    // With a limit, only the best offset + limit rows are kept and the worst of them is on top
+    let mut candidates:BinaryHeap<SortCandidate> = BinaryHeap::new();
+    let mut seq = 0;
+
+    for (part_no, part_info) in part_infos.iter().enumerate() {
+        if let Some(wanted_count) = wanted_count {
+            if is_ts_order && wanted_count > 0 && candidates.len() >= wanted_count {
+                let cannot_improve = match (&candidates.peek().unwrap().key, order) {
+                    (&GroupKey::UInt(worst_ts), &SortOrder::Asc) => part_info.min_ts > worst_ts,
+                    (&GroupKey::UInt(worst_ts), &SortOrder::Desc) => part_info.max_ts < worst_ts,
+                    _ => false
+                };
+
+                if cannot_improve {
+                    break;
+                }
+            }
+        }
+
+        let mut cache = BlockCache::new(part_info);
+        let combined_consumer = part_scan_and_combine(manager, part_info, &mut cache, req)?;
+
+        let keys = group_keys_by_columns(manager, &mut cache, &combined_consumer, &vec![order_col])?;
+        for (mut key, offset) in keys.into_iter().zip(combined_consumer.matching_offsets.iter()) {
+            candidates.push(SortCandidate { key: key.remove(0), seq: seq, part_no: part_no, offset: *offset, order: order });
+            seq += 1;
+
+            if let Some(wanted_count) = wanted_count {
+                if candidates.len() > wanted_count {
+                    candidates.pop();
+                }
+            }
+        }
+    }
+
+    Ok(candidates.into_sorted_vec().into_iter().skip(req.offset as usize).map(|c| (c.part_no, c.offset)).collect())
+}
+
+// All rows to be returned for the request, in order, without materializing them yet
+pub fn scan_matching_rows(manager: &Manager, req : &ScanRequest) -> error::Result<(Vec<PartitionInfo>, Vec<(usize, u32)>)> {
+    check_scan_request(&manager.catalog, req)?;
+
+    let mut part_infos = scanned_partitions(manager, req.partition_id, req.min_ts, req.max_ts)?;
+
+    if let Some((order_col, ref order)) = req.order_by {
+        let rows = scan_ordered_rows(manager, &mut part_infos, req, order_col, order)?;
+        return Ok((part_infos, rows));
+    }
+
+    let mut rows:Vec<(usize, u32)> = Vec::new();
+    let mut skipped_count = 0 as usize;
+
+    for (part_no, part_info) in part_infos.iter().enumerate() {
+        if req.limit.map_or(false, |limit| rows.len() >= limit as usize) {
+            break;
+        }
+
+        let mut cache = BlockCache::new(part_info);
+        let combined_consumer = part_scan_and_combine(manager, part_info, &mut cache, req)?;
+
+        let skipped_here = cmp::min(req.offset as usize - skipped_count, combined_consumer.matching_offsets.len());
+        skipped_count += skipped_here;
+
+        for offset in &combined_consumer.matching_offsets[skipped_here..] {
+            if req.limit.map_or(false, |limit| rows.len() >= limit as usize) {
+                break;
+            }
+            rows.push((part_no, *offset));
+        }
+    }
+
+    Ok((part_infos, rows))
+}
+
+pub fn part_scan_and_materialize(manager: &Manager, req : &ScanRequest) -> error::Result<ScanResultMessage> {
     let scan_duration = Instant::now();
 
-    let part_info = &manager.find_partition_info(req.partition_id);
-    let mut cache = BlockCache::new(part_info);
+    check_scan_request(&manager.catalog, req)?;
+
+    let mut part_infos = scanned_partitions(manager, req.partition_id, req.min_ts, req.max_ts)?;
+
+    if let Some((order_col, ref order)) = req.order_by {
+        let rows = scan_ordered_rows(manager, &mut part_infos, req, order_col, order)?;
+        let scan_msg = materialize_rows(manager, &part_infos, &rows, &req.projection)?;
+
+        println!("Scanning, ordering and materializing {} elements took {:?}", scan_msg.row_count, scan_duration.elapsed());
+
+        return Ok(scan_msg);
+    }
 
-    let combined_consumer = part_scan_and_combine(manager, part_info, &mut cache, req);
+    let mut scan_msg = ScanResultMessage::empty_projection(&manager.catalog, &req.projection);
+    let mut total_matched = 0;
+    let mut skipped_count = 0 as usize;
 
-    let mut scan_msg = ScanResultMessage::new();
-    combined_consumer.materialize(&manager, &mut cache, &req.projection, &mut scan_msg);
+    for part_info in &part_infos {
+        if req.limit.map_or(false, |limit| scan_msg.row_count >= limit) {
+            break;
+        }
+
+        let mut cache = BlockCache::new(part_info);
+
+        let mut combined_consumer = part_scan_and_combine(manager, part_info, &mut cache, req)?;
+        total_matched += combined_consumer.matching_offsets.len();
+
+        // Skip and limit before materializing, so only the returned rows are copied
+        let skipped_here = cmp::min(req.offset as usize - skipped_count, combined_consumer.matching_offsets.len());
+        combined_consumer.matching_offsets.drain(..skipped_here);
+        skipped_count += skipped_here;
+
+        if let Some(limit) = req.limit {
+            combined_consumer.matching_offsets.truncate((limit - scan_msg.row_count) as usize);
+        }
+
+        if combined_consumer.matching_offsets.is_empty() {
+            continue;
+        }
+
+        let mut part_msg = ScanResultMessage::new();
+        combined_consumer.materialize(&manager, &mut cache, &req.projection, &mut part_msg)?;
+
+        if scan_msg.row_count == 0 {
+            scan_msg = part_msg;
+        } else {
+            scan_msg.append(&part_msg);
+        }
+    }
 
-    let total_matched = combined_consumer.matching_offsets.len();
     let total_materialized = scan_msg.row_count;
 
     println!("Scanning and matching/materializing {}/{} elements took {:?}", total_matched, total_materialized, scan_duration.elapsed());
 
-    scan_msg
+    Ok(scan_msg)
+}
+
+pub fn part_scan_and_aggregate(manager: &Manager, req : &AggregateRequest) -> error::Result<AggregateResponse> {
+    let scan_duration = Instant::now();
+
+    let scan_req = req.selection.to_scan_request();
+    check_scan_request(&manager.catalog, &scan_req)?;
+    check_aggregates(&manager.catalog, &req.aggregates)?;
+    let mut states:Vec<AggregateState> = req.aggregates.iter().map(|&(_, ref function)| AggregateState::new(function)).collect();
+    let mut row_count = 0;
+
+    for part_info in &scanned_partitions(manager, scan_req.partition_id, scan_req.min_ts, scan_req.max_ts)? {
+        let mut cache = BlockCache::new(part_info);
+
+        let combined_consumer = part_scan_and_combine(manager, part_info, &mut cache, &scan_req)?;
+        row_count += combined_consumer.matching_offsets.len() as u64;
+
+        for (&(col_index, _), state) in req.aggregates.iter().zip(states.iter_mut()) {
+            let block = cache.get_cached_or_load(manager, col_index)?;
+            block.for_each_matching(&combined_consumer, |_, v| state.add(v));
+        }
+    }
+
+    println!("Scanning and aggregating {} elements took {:?}", row_count, scan_duration.elapsed());
+
+    Ok(AggregateResponse {
+        row_count: row_count,
+        values: states.iter().map(|state| state.result()).collect()
+    })
+}
+
+// Updates grouping with the matching rows, keys hold group key of each matching row
+fn group_matching(manager : &Manager, cache : &mut BlockCache, consumer : &BlockScanConsumer, keys : Vec<Vec<GroupKey>>, aggregates : &Vec<(u32, AggregateFunction)>, grouping : &mut GroupedAggregation) -> error::Result<()> {
+    let row_groups:Vec<usize> = keys.into_iter().map(|key| grouping.add_row(key)).collect();
+
+    for (aggregate_index, &(col_index, _)) in aggregates.iter().enumerate() {
+        let block = cache.get_cached_or_load(manager, col_index)?;
+        block.for_each_matching(consumer, |position, v| grouping.states[row_groups[position]][aggregate_index].add(v));
+    }
+
+    Ok(())
+}
+
+fn group_keys_by_columns(manager : &Manager, cache : &mut BlockCache, consumer : &BlockScanConsumer, key_blocks : &Vec<u32>) -> error::Result<Vec<Vec<GroupKey>>> {
+    let mut keys:Vec<Vec<GroupKey>> = vec![vec![GroupKey::Null; key_blocks.len()]; consumer.matching_offsets.len()];
+
+    for (key_index, col_index) in key_blocks.iter().enumerate() {
+        let block = cache.get_cached_or_load(manager, *col_index)?;
+        block.for_each_matching(consumer, |position, v| keys[position][key_index] = GroupKey::from_scalar(v));
+    }
+
+    Ok(keys)
+}
+
+fn group_keys_by_ts_bucket(manager : &Manager, cache : &mut BlockCache, consumer : &BlockScanConsumer, bucket_width : u64) -> error::Result<Vec<Vec<GroupKey>>> {
+    let mut keys:Vec<Vec<GroupKey>> = vec![vec![GroupKey::Null]; consumer.matching_offsets.len()];
+
+    let block = cache.get_cached_or_load(manager, 0)?; // ts
+    block.for_each_matching(consumer, |position, v| if let ScalarValue::UInt(ts) = v {
+        keys[position][0] = GroupKey::UInt(ts - ts % bucket_width);
+    });
+
+    Ok(keys)
+}
+
+pub fn part_scan_and_group(manager: &Manager, req : &GroupByRequest) -> error::Result<GroupByResponse> {
+    let scan_duration = Instant::now();
+
+    let scan_req = req.selection.to_scan_request();
+    check_scan_request(&manager.catalog, &scan_req)?;
+    check_aggregates(&manager.catalog, &req.aggregates)?;
+    for col_index in &req.group_by {
+        check_column(&manager.catalog, *col_index)?;
+    }
+    let mut grouping = GroupedAggregation::new(&req.aggregates);
+    let mut total_matched = 0;
+
+    for part_info in &scanned_partitions(manager, scan_req.partition_id, scan_req.min_ts, scan_req.max_ts)? {
+        let mut cache = BlockCache::new(part_info);
+
+        let combined_consumer = part_scan_and_combine(manager, part_info, &mut cache, &scan_req)?;
+        let keys = group_keys_by_columns(manager, &mut cache, &combined_consumer, &req.group_by)?;
+        group_matching(manager, &mut cache, &combined_consumer, keys, &req.aggregates, &mut grouping)?;
+
+        total_matched += combined_consumer.matching_offsets.len();
+    }
+
+    println!("Scanning and grouping {} elements took {:?}", total_matched, scan_duration.elapsed());
+
+    Ok(GroupByResponse {
+        groups: grouping.into_rows()
+    })
+}
+
+pub fn part_scan_and_histogram(manager: &Manager, req : &HistogramRequest) -> error::Result<HistogramResponse> {
+    let scan_duration = Instant::now();
+
+    if req.bucket_width == 0 {
+        return Err(Error::BadPayload(String::from("Bucket width must be greater than zero")));
+    }
+
+    let scan_req = req.selection.to_scan_request();
+    check_scan_request(&manager.catalog, &scan_req)?;
+    check_aggregates(&manager.catalog, &req.aggregates)?;
+    let mut grouping = GroupedAggregation::new(&req.aggregates);
+    let mut total_matched = 0;
+
+    for part_info in &scanned_partitions(manager, scan_req.partition_id, scan_req.min_ts, scan_req.max_ts)? {
+        let mut cache = BlockCache::new(part_info);
+
+        let combined_consumer = part_scan_and_combine(manager, part_info, &mut cache, &scan_req)?;
+        let keys = group_keys_by_ts_bucket(manager, &mut cache, &combined_consumer, req.bucket_width)?;
+        group_matching(manager, &mut cache, &combined_consumer, keys, &req.aggregates, &mut grouping)?;
+
+        total_matched += combined_consumer.matching_offsets.len();
+    }
+
+    println!("Scanning and bucketing {} elements took {:?}", total_matched, scan_duration.elapsed());
+
+    let buckets = grouping.into_rows().into_iter().map(|row| HistogramBucket {
+        start_ts: match row.key[0] {
+            GroupKey::UInt(start_ts) => start_ts,
+            _ => panic!("Bucket not based on ts")
+        },
+        row_count: row.row_count,
+        values: row.values
+    }).collect();
+
+    Ok(HistogramResponse {
+        bucket_width: req.bucket_width,
+        buckets: buckets
+    })
 }
 
 #[test]
@@ -366,15 +1509,21 @@ fn api_scan_message_serialization() {
     let scan_req = ScanRequest {
         min_ts: 100 as u64,
         max_ts: 200 as u64,
-        partition_id: 0,
+        partition_id: None,
         filters: vec![
             ScanFilter {
                 column: 5,
                 op: ScanComparison::GtEq,
                 val: 1000 as u64,
-                str_val: vec![]
+                str_val: vec![],
+                vals: vec![],
+                str_vals: vec![]
             }
         ],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
         projection: vec![0,1,2,3]
     };
 
@@ -397,21 +1546,22 @@ fn api_scan_message_serialization() {
 fn api_refresh_catalog_serialization() {
     let pseudo_response = RefreshCatalogResponse{
         columns: vec![
-            Column {
+            (0, Column {
                 data_type: BlockType::Int64Dense,
                 name: String::from("ts")
-            },
-            Column {
+            }),
+            (2, Column {
                 data_type: BlockType::Int32Sparse,
                 name: String::from("source")
-            }
+            })
         ],
         available_partitions: vec![
             PartitionInfo{
                 min_ts: 100,
                 max_ts: 200,
                 id: 999,
-                location: String::from("/foo/bar")
+                location: String::from("/foo/bar"),
+                block_stats: vec![]
             }
         ]
     };
@@ -420,3 +1570,808 @@ fn api_refresh_catalog_serialization() {
     println!("String response: {:?}", serialize(&x, Infinite).unwrap());
     println!("Pseudo catalog refresh response: {:?}", serialize(&pseudo_response, Infinite).unwrap());
 }
+
+// Creates a fresh database at given location with two stored partitions, each having 4 rows spaced by 1000
+#[cfg(test)]
+pub fn create_test_manager(db_home : &str) -> Manager {
+    use std::fs;
+    use int_blocks::StringBlock;
+
+    fs::remove_dir_all(db_home).ok();
+
+    let mut manager = Manager::new(String::from(db_home));
+    manager.catalog.add_column(BlockType::Int64Dense, String::from("ts"));
+    manager.catalog.add_column(BlockType::Int64Dense, String::from("source"));
+    manager.catalog.add_column(BlockType::Int32Sparse, String::from("pattern_id"));
+    manager.catalog.add_column(BlockType::String, String::from("p1"));
+    manager.store_catalog().unwrap();
+
+    for base_ts in vec![1495490000 as u64 * 1000000, 1495500000 as u64 * 1000000] {
+        manager.insert(&InsertMessage {
+            row_count: 4,
+            col_count: 4,
+            col_types: vec![(0, BlockType::Int64Dense), (1, BlockType::Int64Dense), (2, BlockType::Int32Sparse), (3, BlockType::String)],
+            blocks: vec![
+                Block::Int64Dense(Int64DenseBlock{
+                    data: vec![base_ts, base_ts+1000, base_ts+2000, base_ts+3000]
+                }),
+                Block::Int64Dense(Int64DenseBlock{
+                    data: vec![1, 2, 1, 3]
+                }),
+                Block::Int32Sparse(Int32SparseBlock{
+                    data: vec![(0, 5), (2, 5), (3, 7)]
+                }),
+                Block::StringBlock(StringBlock{
+                    index_data: vec![(1,0),(2,1),(3,2)],
+                    str_data: "xyz".as_bytes().to_vec()
+                })
+            ]
+        }).unwrap();
+        manager.dump_in_mem_partition().unwrap();
+    }
+
+    manager
+}
+
+#[test]
+fn scan_fills_dense_columns_added_later() {
+    let mut manager = create_test_manager("/tmp/hyena_test_dense_added_later");
+    manager.add_column(BlockType::Int64Dense, String::from("count")).unwrap();
+    manager.add_column(BlockType::Float64Dense, String::from("ratio")).unwrap();
+
+    let req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: None,
+        filters: vec![ScanFilter { column: 4, op: ScanComparison::Eq, val: 0, str_val: vec![], vals: vec![], str_vals: vec![] }],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![4, 5]
+    };
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(8, msg.row_count);
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![0; 8] }), msg.blocks[0]);
+
+    let req = AggregateRequest {
+        selection: ScanSelection {
+            min_ts: 0,
+            max_ts: u64::max_value(),
+            partition_id: None,
+            filters: vec![],
+            filter_expression: None
+        },
+        aggregates: vec![(4, AggregateFunction::Sum), (5, AggregateFunction::Max)]
+    };
+    assert_eq!(AggregateResponse {
+        row_count: 8,
+        values: vec![AggregateValue::UInt(0), AggregateValue::Float(0.0)]
+    }, part_scan_and_aggregate(&manager, &req).unwrap());
+}
+
+#[test]
+fn scan_honors_time_range() {
+    let manager = create_test_manager("/tmp/hyena_test_scan_time_range");
+    let base_ts = manager.catalog.available_partitions[0].min_ts;
+
+    let mut req = ScanRequest {
+        min_ts: base_ts + 1000,
+        max_ts: base_ts + 2000,
+        partition_id: Some(manager.catalog.available_partitions[0].id),
+        filters: vec![],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![0, 2]
+    };
+
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(2, msg.row_count);
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![base_ts+1000, base_ts+2000] }), msg.blocks[0]);
+    assert_eq!(Block::Int32Sparse(Int32SparseBlock{ data: vec![(1, 5)] }), msg.blocks[1]);
+
+    // Time range combined with a regular filter
+    req.filters.push(ScanFilter { column: 1, op: ScanComparison::Eq, val: 2, str_val: vec![], vals: vec![], str_vals: vec![] });
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(1, msg.row_count);
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![base_ts+1000] }), msg.blocks[0]);
+
+    // Second partition does not overlap at all
+    req.filters.clear();
+    req.partition_id = Some(manager.catalog.available_partitions[1].id);
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(ScanResultMessage::empty_projection(&manager.catalog, &req.projection), msg);
+    assert_eq!(2, msg.col_count);
+}
+
+#[test]
+fn scan_combines_all_overlapping_partitions() {
+    use int_blocks::StringBlock;
+
+    let manager = create_test_manager("/tmp/hyena_test_scan_all_partitions");
+    let first_ts = manager.catalog.available_partitions[0].min_ts;
+    let second_ts = manager.catalog.available_partitions[1].min_ts;
+
+    let req = ScanRequest {
+        min_ts: first_ts + 2000,
+        max_ts: second_ts + 1000,
+        partition_id: None,
+        filters: vec![],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![0, 2, 3]
+    };
+
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(4, msg.row_count);
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![first_ts+2000, first_ts+3000, second_ts, second_ts+1000] }), msg.blocks[0]);
+    assert_eq!(Block::Int32Sparse(Int32SparseBlock{ data: vec![(0, 5), (1, 7), (2, 5)] }), msg.blocks[1]);
+    assert_eq!(Block::StringBlock(StringBlock{ index_data: vec![(0, 0), (1, 1), (3, 2)], str_data: "yzx".as_bytes().to_vec() }), msg.blocks[2]);
+}
+
+#[test]
+fn scan_includes_in_mem_partition() {
+    let mut manager = create_test_manager("/tmp/hyena_test_scan_in_mem");
+    let last_ts = manager.catalog.available_partitions[1].max_ts;
+
+    manager.insert(&InsertMessage {
+        row_count: 2,
+        col_count: 3,
+        col_types: vec![(0, BlockType::Int64Dense), (1, BlockType::Int64Dense), (2, BlockType::Int32Sparse)],
+        blocks: vec![
+            Block::Int64Dense(Int64DenseBlock{
+                data: vec![last_ts+1000, last_ts+2000]
+            }),
+            Block::Int64Dense(Int64DenseBlock{
+                data: vec![1, 2]
+            }),
+            Block::Int32Sparse(Int32SparseBlock{
+                data: vec![(1, 5)]
+            })
+        ]
+    }).unwrap();
+
+    let req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: None,
+        filters: vec![ScanFilter { column: 2, op: ScanComparison::Eq, val: 5, str_val: vec![], vals: vec![], str_vals: vec![] }],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![0]
+    };
+
+    let first_ts = manager.catalog.available_partitions[0].min_ts;
+    let second_ts = manager.catalog.available_partitions[1].min_ts;
+
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(5, msg.row_count);
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![first_ts, first_ts+2000, second_ts, second_ts+2000, last_ts+2000] }), msg.blocks[0]);
+
+    // And the same once it is flushed
+    manager.dump_in_mem_partition().unwrap();
+    assert_eq!(msg, part_scan_and_materialize(&manager, &req).unwrap());
+}
+
+#[test]
+fn scan_evaluates_filter_expression() {
+    let manager = create_test_manager("/tmp/hyena_test_scan_filter_expression");
+
+    let source_eq = |val| FilterExpression::Filter(ScanFilter { column: 1, op: ScanComparison::Eq, val: val, str_val: vec![], vals: vec![], str_vals: vec![] });
+
+    // source = 2 OR (pattern_id = 5 AND p1 != "x")
+    let mut req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: Some(manager.catalog.available_partitions[0].id),
+        filters: vec![],
+        filter_expression: Some(FilterExpression::Or(vec![
+            source_eq(2),
+            FilterExpression::And(vec![
+                FilterExpression::Filter(ScanFilter { column: 2, op: ScanComparison::Eq, val: 5, str_val: vec![], vals: vec![], str_vals: vec![] }),
+                FilterExpression::Filter(ScanFilter { column: 3, op: ScanComparison::NotEq, val: 0, str_val: "x".as_bytes().to_vec(), vals: vec![], str_vals: vec![] })
+            ])
+        ])),
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![1]
+    };
+
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![2, 1] }), msg.blocks[0]);
+
+    req.filter_expression = Some(FilterExpression::Not(Box::new(source_eq(1))));
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![2, 3] }), msg.blocks[0]);
+
+    // NOT matches rows where the sparse column is missing too
+    req.filter_expression = Some(FilterExpression::Not(Box::new(
+        FilterExpression::Filter(ScanFilter { column: 3, op: ScanComparison::Eq, val: 0, str_val: "y".as_bytes().to_vec(), vals: vec![], str_vals: vec![] })
+    )));
+    req.filters = vec![ScanFilter { column: 1, op: ScanComparison::Eq, val: 1, str_val: vec![], vals: vec![], str_vals: vec![] }];
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(1, msg.row_count);
+}
+
+#[test]
+fn aggregates_matching_rows() {
+    let manager = create_test_manager("/tmp/hyena_test_aggregate");
+
+    let mut req = AggregateRequest {
+        selection: ScanSelection {
+            min_ts: 0,
+            max_ts: u64::max_value(),
+            partition_id: None,
+            filters: vec![],
+            filter_expression: None
+        },
+        aggregates: vec![
+            (0, AggregateFunction::Count),
+            (1, AggregateFunction::Sum),
+            (2, AggregateFunction::Min),
+            (2, AggregateFunction::Max),
+            (2, AggregateFunction::Avg),
+            (3, AggregateFunction::Count)
+        ]
+    };
+
+    assert_eq!(AggregateResponse {
+        row_count: 8,
+        values: vec![
+            AggregateValue::UInt(8),
+            AggregateValue::UInt(14),
+            AggregateValue::UInt(5),
+            AggregateValue::UInt(7),
+            AggregateValue::Float(34.0 / 6.0),
+            AggregateValue::UInt(6)
+        ]
+    }, part_scan_and_aggregate(&manager, &req).unwrap());
+
+    req.selection.filters.push(ScanFilter { column: 1, op: ScanComparison::Eq, val: 1, str_val: vec![], vals: vec![], str_vals: vec![] });
+    req.aggregates = vec![(0, AggregateFunction::Count), (2, AggregateFunction::Sum), (3, AggregateFunction::Count)];
+
+    assert_eq!(AggregateResponse {
+        row_count: 4,
+        values: vec![AggregateValue::UInt(4), AggregateValue::UInt(20), AggregateValue::UInt(2)]
+    }, part_scan_and_aggregate(&manager, &req).unwrap());
+
+    // Strings can be only counted
+    req.aggregates = vec![(3, AggregateFunction::Min)];
+    assert_eq!(3, part_scan_and_aggregate(&manager, &req).unwrap_err().status());
+}
+
+#[test]
+fn selection_keeps_wire_format_of_requests() {
+    let filters = vec![ScanFilter { column: 1, op: ScanComparison::Eq, val: 1, str_val: vec![], vals: vec![], str_vals: vec![] }];
+    let req = AggregateRequest {
+        selection: ScanSelection {
+            min_ts: 10,
+            max_ts: 20,
+            partition_id: Some(30),
+            filters: filters.to_owned(),
+            filter_expression: None
+        },
+        aggregates: vec![(1, AggregateFunction::Sum)]
+    };
+
+    // Same as when the fields were not grouped
+    let flat = (10u64, 20u64, Some(30u64), filters, None::<FilterExpression>, vec![(1u32, AggregateFunction::Sum)]);
+    assert_eq!(serialize(&flat, Infinite).unwrap(), serialize(&req, Infinite).unwrap());
+}
+
+#[test]
+fn groups_matching_rows() {
+    let manager = create_test_manager("/tmp/hyena_test_group_by");
+
+    let mut req = GroupByRequest {
+        selection: ScanSelection {
+            min_ts: 0,
+            max_ts: u64::max_value(),
+            partition_id: None,
+            filters: vec![],
+            filter_expression: None
+        },
+        group_by: vec![1],
+        aggregates: vec![(2, AggregateFunction::Sum)]
+    };
+
+    assert_eq!(GroupByResponse {
+        groups: vec![
+            GroupedRow { key: vec![GroupKey::UInt(1)], row_count: 4, values: vec![AggregateValue::UInt(20)] },
+            GroupedRow { key: vec![GroupKey::UInt(2)], row_count: 2, values: vec![AggregateValue::Null] },
+            GroupedRow { key: vec![GroupKey::UInt(3)], row_count: 2, values: vec![AggregateValue::UInt(14)] }
+        ]
+    }, part_scan_and_group(&manager, &req).unwrap());
+
+    req.group_by = vec![1, 3];
+    req.aggregates = vec![];
+    req.selection.filters.push(ScanFilter { column: 2, op: ScanComparison::Eq, val: 5, str_val: vec![], vals: vec![], str_vals: vec![] });
+
+    assert_eq!(GroupByResponse {
+        groups: vec![
+            GroupedRow { key: vec![GroupKey::UInt(1), GroupKey::Null], row_count: 2, values: vec![] },
+            GroupedRow { key: vec![GroupKey::UInt(1), GroupKey::Str("y".as_bytes().to_vec())], row_count: 2, values: vec![] }
+        ]
+    }, part_scan_and_group(&manager, &req).unwrap());
+}
+
+#[test]
+fn buckets_matching_rows_by_ts() {
+    let manager = create_test_manager("/tmp/hyena_test_histogram");
+    let first_ts = manager.catalog.available_partitions[0].min_ts;
+    let second_ts = manager.catalog.available_partitions[1].min_ts;
+
+    let mut req = HistogramRequest {
+        selection: ScanSelection {
+            min_ts: 0,
+            max_ts: u64::max_value(),
+            partition_id: None,
+            filters: vec![],
+            filter_expression: None
+        },
+        bucket_width: 2000,
+        aggregates: vec![(1, AggregateFunction::Sum)]
+    };
+
+    let bucket = |start_ts, row_count, values| HistogramBucket { start_ts: start_ts, row_count: row_count, values: values };
+
+    assert_eq!(HistogramResponse {
+        bucket_width: 2000,
+        buckets: vec![
+            bucket(first_ts, 2, vec![AggregateValue::UInt(3)]),
+            bucket(first_ts + 2000, 2, vec![AggregateValue::UInt(4)]),
+            bucket(second_ts, 2, vec![AggregateValue::UInt(3)]),
+            bucket(second_ts + 2000, 2, vec![AggregateValue::UInt(4)])
+        ]
+    }, part_scan_and_histogram(&manager, &req).unwrap());
+
+    req.selection.min_ts = first_ts + 1000;
+    req.selection.max_ts = first_ts + 1000000;
+    req.bucket_width = 1000000;
+    req.aggregates = vec![];
+    req.selection.filters.push(ScanFilter { column: 2, op: ScanComparison::Eq, val: 5, str_val: vec![], vals: vec![], str_vals: vec![] });
+
+    assert_eq!(HistogramResponse {
+        bucket_width: 1000000,
+        buckets: vec![bucket(first_ts, 1, vec![])]
+    }, part_scan_and_histogram(&manager, &req).unwrap());
+}
+
+#[test]
+fn scan_applies_limit_offset_and_order() {
+    let manager = create_test_manager("/tmp/hyena_test_scan_limit_order");
+    let first_ts = manager.catalog.available_partitions[0].min_ts;
+    let second_ts = manager.catalog.available_partitions[1].min_ts;
+
+    let mut req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: None,
+        filters: vec![],
+        filter_expression: None,
+        offset: 3,
+        limit: Some(3),
+        order_by: None,
+        projection: vec![0]
+    };
+
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![first_ts+3000, second_ts, second_ts+1000] }), msg.blocks[0]);
+
+    // Latest rows first
+    req.offset = 0;
+    req.order_by = Some((0, SortOrder::Desc));
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![second_ts+3000, second_ts+2000, second_ts+1000] }), msg.blocks[0]);
+
+    // Ordering by sparse column interleaves partitions and puts the rows without it last
+    req.offset = 1;
+    req.limit = Some(6);
+    req.order_by = Some((2, SortOrder::Asc));
+    req.projection = vec![0, 2];
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(6, msg.row_count);
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![first_ts+2000, second_ts, second_ts+2000, first_ts+3000, second_ts+3000, first_ts+1000] }), msg.blocks[0]);
+    assert_eq!(Block::Int32Sparse(Int32SparseBlock{ data: vec![(0, 5), (1, 5), (2, 5), (3, 7), (4, 7)] }), msg.blocks[1]);
+
+    // No limit at all
+    req.offset = 0;
+    req.limit = None;
+    req.order_by = Some((0, SortOrder::Desc));
+    req.projection = vec![0];
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(8, msg.row_count);
+}
+
+#[test]
+fn rejects_invalid_requests() {
+    use int_blocks::StringBlock;
+
+    let mut manager = create_test_manager("/tmp/hyena_test_invalid_requests");
+    let part_id = manager.catalog.available_partitions[0].id;
+
+    let req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: None,
+        filters: vec![ScanFilter { column: 2, op: ScanComparison::Eq, val: 5, str_val: vec![], vals: vec![], str_vals: vec![] }],
+        filter_expression: Some(FilterExpression::Not(Box::new(FilterExpression::Filter(ScanFilter { column: 99, op: ScanComparison::Eq, val: 5, str_val: vec![], vals: vec![], str_vals: vec![] })))),
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![0]
+    };
+
+    match part_scan_and_materialize(&manager, &req) {
+        Err(Error::UnknownColumn(99)) => (),
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    // Strings can't be upserted to an int column
+    let compaction_req = DataCompactionRequest {
+        partition_id: part_id,
+        filters: vec![],
+        renamed_columns: vec![],
+        dropped_columns: vec![],
+        upserted_data: PartialInsertMessage {
+            col_count: 1,
+            col_types: vec![(2, BlockType::Int32Sparse)],
+            blocks: vec![Block::StringBlock(StringBlock { index_data: vec![(0, 0)], str_data: "x".as_bytes().to_vec() })]
+        }
+    };
+    assert_eq!(3, handle_data_compaction(&mut manager, &compaction_req).unwrap_err().status());
+
+    // Dense columns can't lose or move their values
+    let drop_dense_req = DataCompactionRequest { dropped_columns: vec![1], upserted_data: PartialInsertMessage { col_count: 0, col_types: vec![], blocks: vec![] }, ..compaction_req };
+    assert_eq!(3, handle_data_compaction(&mut manager, &drop_dense_req).unwrap_err().status());
+    let move_dense_req = DataCompactionRequest { dropped_columns: vec![], renamed_columns: vec![(1, 0)], ..drop_dense_req };
+    assert_eq!(3, handle_data_compaction(&mut manager, &move_dense_req).unwrap_err().status());
+    let move_to_dense_req = DataCompactionRequest { renamed_columns: vec![(2, 1)], ..move_dense_req };
+    assert_eq!(3, handle_data_compaction(&mut manager, &move_to_dense_req).unwrap_err().status());
+
+    let insert_msg = InsertMessage {
+        row_count: 1,
+        col_count: 1,
+        col_types: vec![(0, BlockType::Int64Dense)],
+        blocks: vec![Block::Int64Dense(Int64DenseBlock { data: vec![1, 2] })]
+    };
+    assert_eq!(1, manager.insert(&insert_msg).unwrap_err().status());
+
+    // Unknown partition is reported rather than scanned as if it was empty
+    let unknown_part_req = ScanRequest { partition_id: Some(part_id + 1), filter_expression: None, ..req };
+    match part_scan_and_materialize(&manager, &unknown_part_req) {
+        Err(Error::UnknownPartition(id)) => assert_eq!(part_id + 1, id),
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    // Garbage payload ends up as an error response
+    let api_msg = ApiMessage { op_type: ApiOperation::Scan, payload: vec![1, 2, 3] };
+    let resp:ApiResponse = deserialize(&ApiResponse::create_as_buf(api_msg.extract_scan_request().map(|_| vec![]))[..]).unwrap();
+    let error_resp:ErrorResponse = deserialize(&resp.payload[..]).unwrap();
+    assert_eq!(1, resp.status);
+    assert_eq!(1, error_resp.status);
+
+    // So does a message of some other operation
+    let api_msg = ApiMessage { op_type: ApiOperation::Insert, payload: serialize(&unknown_part_req, Infinite).unwrap() };
+    assert_eq!(1, api_msg.extract_scan_request().unwrap_err().status());
+}
+
+#[test]
+fn scan_filters_bitmap_column() {
+    use int_blocks::BitmapBlock;
+
+    let mut manager = create_test_manager("/tmp/hyena_test_bitmap_scan");
+    manager.add_column(BlockType::Bitmap, String::from("flag")).unwrap();
+    let last_ts = manager.catalog.available_partitions[1].max_ts;
+
+    let mut flags = BitmapBlock::new();
+    flags.data.insert(1);
+    manager.insert(&InsertMessage {
+        row_count: 3,
+        col_count: 3,
+        col_types: vec![(0, BlockType::Int64Dense), (1, BlockType::Int64Dense), (4, BlockType::Bitmap)],
+        blocks: vec![
+            Block::Int64Dense(Int64DenseBlock{ data: vec![last_ts+1000, last_ts+2000, last_ts+3000] }),
+            Block::Int64Dense(Int64DenseBlock{ data: vec![1, 1, 1] }),
+            Block::Bitmap(flags)
+        ]
+    }).unwrap();
+    manager.dump_in_mem_partition().unwrap();
+
+    let mut req = ScanRequest {
+        min_ts: last_ts+1,
+        max_ts: u64::max_value(),
+        partition_id: None,
+        filters: vec![ScanFilter { column: 4, op: ScanComparison::Eq, val: 0, str_val: vec![], vals: vec![], str_vals: vec![] }],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![0, 4]
+    };
+
+    // Rows without the flag set are false
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![last_ts+1000, last_ts+3000] }), msg.blocks[0]);
+    assert_eq!(Block::Bitmap(BitmapBlock::new()), msg.blocks[1]);
+
+    req.filters[0].val = 1;
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![last_ts+2000] }), msg.blocks[0]);
+    assert_eq!(1, msg.blocks[1].len());
+}
+
+#[test]
+fn rejects_invalid_string_patterns() {
+    let manager = create_test_manager("/tmp/hyena_test_string_patterns");
+
+    let mut req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: None,
+        filters: vec![ScanFilter { column: 3, op: ScanComparison::RegexIgnoreCase, val: 0, str_val: "^[XY]$".as_bytes().to_vec(), vals: vec![], str_vals: vec![] }],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![0]
+    };
+    assert_eq!(4, part_scan_and_materialize(&manager, &req).unwrap().row_count);
+
+    req.filters[0].str_val = "[x".as_bytes().to_vec();
+    assert_eq!(1, part_scan_and_materialize(&manager, &req).unwrap_err().status());
+
+    // Not a string column
+    req.filters[0] = ScanFilter { column: 2, op: ScanComparison::Contains, val: 5, str_val: vec![], vals: vec![], str_vals: vec![] };
+    assert_eq!(3, part_scan_and_materialize(&manager, &req).unwrap_err().status());
+}
+
+#[test]
+fn scan_filters_by_value_list_and_range() {
+    let manager = create_test_manager("/tmp/hyena_test_value_list_and_range");
+    let first_ts = manager.catalog.available_partitions[0].min_ts;
+
+    let mut req = ScanRequest::new(vec![1])
+        .filters(vec![ScanFilter::new_multi(1, ScanComparison::In, vec![2, 3])]);
+
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![2, 3, 2, 3] }), msg.blocks[0]);
+
+    req.filters = vec![
+        ScanFilter::new_multi(0, ScanComparison::BetweenHalfOpen, vec![first_ts+1000, first_ts+3000]),
+        ScanFilter::new_str_multi(3, ScanComparison::In, vec!["x".as_bytes().to_vec(), "y".as_bytes().to_vec()])
+    ];
+    req.projection = vec![0];
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![first_ts+1000, first_ts+2000] }), msg.blocks[0]);
+
+    // Range without both bounds
+    req.filters[0].vals.pop();
+    assert_eq!(1, part_scan_and_materialize(&manager, &req).unwrap_err().status());
+}
+
+#[test]
+fn scan_filters_by_presence() {
+    let manager = create_test_manager("/tmp/hyena_test_presence");
+    let first_ts = manager.catalog.available_partitions[0].min_ts;
+    let second_ts = manager.catalog.available_partitions[1].min_ts;
+
+    let presence = |column, op| ScanFilter { column: column, op: op, val: 0, str_val: vec![], vals: vec![], str_vals: vec![] };
+
+    let mut req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: None,
+        filters: vec![presence(2, ScanComparison::Exists)],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![0]
+    };
+
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![first_ts, first_ts+2000, first_ts+3000, second_ts, second_ts+2000, second_ts+3000] }), msg.blocks[0]);
+
+    req.filters = vec![presence(3, ScanComparison::NotExists)];
+    let msg = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![first_ts, second_ts] }), msg.blocks[0]);
+
+    // Dense columns are set in every row
+    req.filters = vec![presence(1, ScanComparison::NotExists)];
+    assert_eq!(0, part_scan_and_materialize(&manager, &req).unwrap().row_count);
+}
+
+#[test]
+fn scan_skips_blocks_using_zone_maps() {
+    use partition::BlockStats;
+
+    let mut manager = create_test_manager("/tmp/hyena_test_zone_maps");
+    let part_info = manager.catalog.available_partitions[0].to_owned();
+
+    assert_eq!(Some(4), part_info.row_count());
+    assert_eq!(Some(&BlockStats { non_null_count: 4, bounds: Some((StatValue::UInt(1), StatValue::UInt(3))) }), part_info.block_stats(1));
+    assert_eq!(Some(&BlockStats { non_null_count: 3, bounds: Some((StatValue::Str(b"x".to_vec()), StatValue::Str(b"z".to_vec()))) }), part_info.block_stats(3));
+
+    let filter = |column, op, val, vals : Vec<u64>| ScanFilter { column: column, op: op, val: val, str_val: vec![], vals: vals, str_vals: vec![] };
+    let zone = |part_info : &PartitionInfo, filter : &ScanFilter| match zone_match(part_info, &BlockType::Int64Dense, filter) {
+        ZoneMatch::NoRows => Some(0),
+        ZoneMatch::AllRows(row_count) => Some(row_count),
+        ZoneMatch::Unknown => None
+    };
+
+    assert_eq!(Some(0), zone(&part_info, &filter(1, ScanComparison::Gt, 3, vec![])));
+    assert_eq!(Some(4), zone(&part_info, &filter(1, ScanComparison::LtEq, 3, vec![])));
+    assert_eq!(None, zone(&part_info, &filter(1, ScanComparison::Eq, 2, vec![])));
+    assert_eq!(Some(0), zone(&part_info, &filter(1, ScanComparison::In, 0, vec![0, 4])));
+    assert_eq!(Some(4), zone(&part_info, &filter(1, ScanComparison::Between, 0, vec![1, 3])));
+    assert_eq!(Some(0), zone(&part_info, &filter(1, ScanComparison::BetweenHalfOpen, 0, vec![0, 1])));
+    // Rows not set never match, so the whole partition can't be taken
+    assert_eq!(None, zone(&part_info, &filter(2, ScanComparison::GtEq, 5, vec![])));
+    assert_eq!(Some(0), zone(&part_info, &filter(2, ScanComparison::Lt, 5, vec![])));
+
+    // Results are the same as of a full scan
+    let mut req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: Some(part_info.id),
+        filters: vec![filter(1, ScanComparison::Gt, 3, vec![])],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![1]
+    };
+    assert_eq!(0, part_scan_and_materialize(&manager, &req).unwrap().row_count);
+
+    req.filters = vec![filter(1, ScanComparison::LtEq, 3, vec![])];
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![1, 2, 1, 3] }), part_scan_and_materialize(&manager, &req).unwrap().blocks[0]);
+
+    // Stats follow the compacted data
+    handle_data_compaction(&mut manager, &DataCompactionRequest {
+        partition_id: part_info.id,
+        filters: vec![],
+        renamed_columns: vec![],
+        dropped_columns: vec![],
+        upserted_data: PartialInsertMessage {
+            col_count: 1,
+            col_types: vec![(2, BlockType::Int32Sparse)],
+            blocks: vec![Block::Int32Sparse(Int32SparseBlock{ data: vec![(0, 9)] })]
+        }
+    }).unwrap();
+
+    let part_info = manager.find_partition_info(part_info.id).unwrap();
+    assert_eq!(Some(&BlockStats { non_null_count: 4, bounds: Some((StatValue::UInt(9), StatValue::UInt(9))) }), part_info.block_stats(2));
+
+    req.filters = vec![filter(2, ScanComparison::Gt, 7, vec![])];
+    assert_eq!(4, part_scan_and_materialize(&manager, &req).unwrap().row_count);
+}
+
+#[test]
+fn zone_maps_keep_string_prefixes() {
+    use partition::BlockStats;
+    use int_blocks::StringBlock;
+
+    let long = |c : u8, len : usize| vec![c; len];
+    let stats_of = |values : Vec<Vec<u8>>| {
+        let mut block = StringBlock::new();
+        for (offset, v) in values.iter().enumerate() {
+            block.append(offset as u32, v);
+        }
+        BlockStats::of(&Block::StringBlock(block))
+    };
+
+    let stats = stats_of(vec![long(b'a', 40), long(b'b', 40)]);
+    let mut max = long(b'b', 31);
+    max.push(b'c');
+    assert_eq!(Some((StatValue::Str(long(b'a', 32)), StatValue::Str(max))), stats.bounds);
+
+    // Trailing 0xFF can't be rounded up
+    let mut value = long(b'c', 30);
+    value.extend(long(0xFF, 10));
+    let mut max = long(b'c', 29);
+    max.push(b'd');
+    assert_eq!(Some((StatValue::Str(value[..32].to_vec()), StatValue::Str(max))), stats_of(vec![value]).bounds);
+    assert_eq!(None, stats_of(vec![long(0xFF, 40)]).bounds);
+
+    let part_info = PartitionInfo {
+        min_ts: 0,
+        max_ts: 0,
+        id: 1,
+        location: String::new(),
+        block_stats: vec![(0, BlockStats { non_null_count: 2, bounds: None }), (1, stats)]
+    };
+    let zone = |op, str_val : Vec<u8>| match zone_match(&part_info, &BlockType::String, &ScanFilter { column: 1, op: op, val: 0, str_val: str_val, vals: vec![], str_vals: vec![] }) {
+        ZoneMatch::NoRows => Some(0),
+        ZoneMatch::AllRows(row_count) => Some(row_count),
+        ZoneMatch::Unknown => None
+    };
+
+    // Values beyond the cut prefixes are still matched
+    assert_eq!(None, zone(ScanComparison::Eq, long(b'b', 40)));
+    assert_eq!(None, zone(ScanComparison::Gt, long(b'b', 32)));
+    assert_eq!(None, zone(ScanComparison::Lt, long(b'a', 33)));
+    assert_eq!(Some(2), zone(ScanComparison::Lt, long(b'c', 1)));
+    assert_eq!(Some(0), zone(ScanComparison::Gt, long(b'c', 1)));
+}
+
+#[test]
+fn scan_partitions_stored_without_zone_maps() {
+    use std::fs::File;
+    use std::io::Write;
+
+    let mut manager = create_test_manager("/tmp/hyena_test_no_zone_maps");
+    let part_info = manager.catalog.available_partitions[0].to_owned();
+
+    let req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: Some(part_info.id),
+        filters: vec![ScanFilter { column: 1, op: ScanComparison::Gt, val: 1, str_val: vec![], vals: vec![], str_vals: vec![] }],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![1, 3]
+    };
+    let expected = part_scan_and_materialize(&manager, &req).unwrap();
+
+    // Catalog stored before the zone maps, partitions had just (min_ts, max_ts, id, location)
+    let partitions:Vec<(u64, u64, u64, String)> = manager.catalog.available_partitions.iter()
+        .map(|part| (part.min_ts, part.max_ts, part.id, part.location.to_owned()))
+        .collect();
+    let stored = serialize(&(manager.catalog.columns.to_owned(), partitions), Infinite).unwrap();
+    File::create(manager.catalog_path()).unwrap().write_all(&stored).unwrap();
+
+    manager.reload_catalog().unwrap();
+    assert_eq!(None, manager.find_partition_info(part_info.id).unwrap().row_count());
+    assert_eq!(expected, part_scan_and_materialize(&manager, &req).unwrap());
+}
+
+#[test]
+fn scan_reports_damaged_blocks() {
+    use std::fs;
+
+    let manager = create_test_manager("/tmp/hyena_test_damaged_block");
+    let part_info = manager.catalog.available_partitions[0].to_owned();
+
+    let mut req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: Some(part_info.id),
+        filters: vec![ScanFilter { column: 1, op: ScanComparison::Eq, val: 2, str_val: vec![], vals: vec![], str_vals: vec![] }],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![0]
+    };
+
+    let block_path = format!("{}/block_1.bin", part_info.location);
+    fs::OpenOptions::new().write(true).open(&block_path).unwrap().set_len(3).unwrap();
+    assert_eq!(6, part_scan_and_materialize(&manager, &req).unwrap_err().status());
+
+    // Blocks which are not needed are not read at all
+    req.filters = vec![];
+    assert_eq!(4, part_scan_and_materialize(&manager, &req).unwrap().row_count);
+
+    let block_path = format!("{}/block_0.bin", part_info.location);
+    fs::OpenOptions::new().write(true).open(&block_path).unwrap().set_len(0).unwrap();
+    assert_eq!(6, part_scan_and_aggregate(&manager, &AggregateRequest {
+        selection: ScanSelection {
+            min_ts: 0,
+            max_ts: u64::max_value(),
+            partition_id: Some(part_info.id),
+            filters: vec![],
+            filter_expression: None
+        },
+        aggregates: vec![(0, AggregateFunction::Count)]
+    }).unwrap_err().status());
+}
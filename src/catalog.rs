@@ -1,8 +1,14 @@
 use int_blocks::Int64DenseBlock;
 use int_blocks::Int64SparseBlock;
 use int_blocks::Int32SparseBlock;
-use partition::Partition;
+use partition::{Partition, BlockStats};
 use int_blocks::Block;
+use error;
+use bincode::{serialize, deserialize_from, Infinite};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::io;
+use std::io::Cursor;
 
 #[repr(u8)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -12,7 +18,47 @@ pub enum BlockType {
     Int32Sparse,
     Int16Sparse,
     Int8Sparse,
-    String
+    String,
+    Float64Dense,
+    Float64Sparse,
+    Float32Sparse,
+    SignedInt64Sparse,
+    SignedInt32Sparse,
+    SignedInt16Sparse,
+    SignedInt8Sparse,
+    Bitmap,
+    DictString
+}
+
+impl BlockType {
+    // Relative capacity of integer types, fully populated sparse columns can become dense
+    fn int_width(&self) -> Option<u8> {
+        match self {
+            &BlockType::Int8Sparse => Some(1),
+            &BlockType::Int16Sparse => Some(2),
+            &BlockType::Int32Sparse => Some(4),
+            &BlockType::Int64Sparse => Some(8),
+            &BlockType::Int64Dense => Some(9),
+            _ => None
+        }
+    }
+
+    pub fn can_widen_to(&self, target: &BlockType) -> bool {
+        match (self.int_width(), target.int_width()) {
+            (Some(width), Some(target_width)) => width < target_width,
+            // Plain strings can be dictionary encoded
+            _ => *self == BlockType::String && *target == BlockType::DictString
+        }
+    }
+
+    pub fn is_string(&self) -> bool {
+        *self == BlockType::String || *self == BlockType::DictString
+    }
+
+    // Dense columns have a value in every row
+    pub fn is_dense(&self) -> bool {
+        *self == BlockType::Int64Dense || *self == BlockType::Float64Dense
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -24,7 +70,59 @@ pub struct Column {
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Catalog {
     pub columns: Vec<Column>,
-    pub available_partitions: Vec<PartitionInfo>
+    pub available_partitions: Vec<PartitionInfo>,
+    // Tombstones of dropped columns - the ids are never reused, as their blocks might still be stored
+    pub dropped_columns: Vec<u32>,
+    // Bumped with each flush, so the log of inserts already stored in a partition is never replayed
+    pub wal_generation: u64,
+    // Partition id -> position in available_partitions, not stored but rebuilt once the catalog is loaded
+    #[serde(skip)]
+    partition_index: HashMap<u64, usize>
+}
+
+// Stored catalog starts with these, followed by the version of its layout (catalogs stored before have no header at all)
+const CATALOG_MAGIC: &'static [u8] = b"HYENACAT";
+const CATALOG_VERSION: u32 = 1;
+
+// Layout of the catalogs stored before it was versioned, only read
+#[derive(Deserialize)]
+struct UnversionedCatalog {
+    columns: Vec<Column>,
+    available_partitions: Vec<UnversionedPartitionInfo>
+}
+
+#[derive(Deserialize)]
+struct UnversionedPartitionInfo {
+    min_ts: u64,
+    max_ts: u64,
+    id: u64,
+    location: String
+}
+
+impl UnversionedPartitionInfo {
+    fn upgrade(self) -> PartitionInfo {
+        PartitionInfo {
+            min_ts: self.min_ts,
+            max_ts: self.max_ts,
+            id: self.id,
+            location: self.location,
+            block_stats: Vec::new()
+        }
+    }
+}
+
+// Some bytes left over mean the data has some other layout
+fn deserialize_whole<T: DeserializeOwned>(buf: &[u8]) -> Option<T> {
+    let mut reader = Cursor::new(buf);
+
+    match deserialize_from(&mut reader, Infinite) {
+        Ok(data) if reader.position() as usize == buf.len() => Some(data),
+        _ => None
+    }
+}
+
+fn damaged_catalog(msg: String) -> error::Error {
+    error::Error::Io(io::Error::new(io::ErrorKind::InvalidData, format!("Catalog is damaged: {}", msg)))
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -32,21 +130,136 @@ pub struct PartitionInfo {
     pub min_ts: u64,
     pub max_ts: u64,
     pub id: u64,
-    pub location: String
+    pub location: String,
+    // Copy of the stored partition zone maps, empty for the in memory partition
+    pub block_stats: Vec<(u32, BlockStats)>
+}
+
+impl PartitionInfo {
+    // Both ranges are inclusive, the same way min_ts/max_ts are computed in Partition::prepare
+    pub fn overlaps(&self, min_ts: u64, max_ts: u64) -> bool {
+        self.min_ts <= max_ts && self.max_ts >= min_ts
+    }
+
+    pub fn is_within(&self, min_ts: u64, max_ts: u64) -> bool {
+        self.min_ts >= min_ts && self.max_ts <= max_ts
+    }
+
+    pub fn block_stats(&self, block_index: u32) -> Option<&BlockStats> {
+        self.block_stats.iter().find(|pair| pair.0 == block_index).map(|pair| &pair.1)
+    }
+
+    // None when the zone maps are not known
+    pub fn row_count(&self) -> Option<u32> {
+        self.block_stats(0).map(|stats| stats.non_null_count)
+    }
+
+    // Empty blocks have no stats
+    pub fn update_block_stats(&mut self, block_index: u32, block: &Block) {
+        self.block_stats.retain(|pair| pair.0 != block_index);
+        if block.len() > 0 {
+            self.block_stats.push((block_index, BlockStats::of(block)));
+        }
+    }
 }
 
 
 impl Catalog {
-//    pub fn column_index(&self, name: &String) -> u32 {
-//        0
-//    }
     pub fn new() -> Catalog {
         Catalog {
             columns: Vec::new(),
-            available_partitions: Vec::new()
+            available_partitions: Vec::new(),
+            dropped_columns: Vec::new(),
+            wal_generation: 0,
+            partition_index: HashMap::new()
+        }
+    }
+
+    pub fn to_bytes(&self) -> error::Result<Vec<u8>> {
+        let mut buf = CATALOG_MAGIC.to_vec();
+        buf.extend(serialize(&CATALOG_VERSION, Infinite)?);
+        buf.extend(serialize(self, Infinite)?);
+        Ok(buf)
+    }
+
+    // Reads any layout stored so far, the partition index still needs to be rebuilt
+    pub fn from_bytes(buf: &[u8]) -> error::Result<Catalog> {
+        if !buf.starts_with(CATALOG_MAGIC) {
+            return match deserialize_whole::<UnversionedCatalog>(buf) {
+                Some(stored) => {
+                    let mut catalog = Catalog::new();
+                    catalog.columns = stored.columns;
+                    catalog.available_partitions = stored.available_partitions.into_iter().map(|part| part.upgrade()).collect();
+                    Ok(catalog)
+                },
+                None => Err(damaged_catalog(String::from("unknown layout")))
+            };
+        }
+
+        let mut reader = Cursor::new(&buf[CATALOG_MAGIC.len()..]);
+        let version:u32 = deserialize_from(&mut reader, Infinite).map_err(|e| damaged_catalog(e.to_string()))?;
+
+        match version {
+            CATALOG_VERSION => deserialize_from(&mut reader, Infinite).map_err(|e| damaged_catalog(e.to_string())),
+            _ => Err(damaged_catalog(format!("unsupported version {}", version)))
+        }
+    }
+
+    pub fn add_partition(&mut self, partition_info: PartitionInfo) {
+        self.partition_index.insert(partition_info.id, self.available_partitions.len());
+        self.available_partitions.push(partition_info);
+    }
+
+    pub fn find_partition(&self, partition_id: u64) -> Option<&PartitionInfo> {
+        self.partition_index.get(&partition_id).map(|index| &self.available_partitions[*index])
+    }
+
+    pub fn find_partition_mut(&mut self, partition_id: u64) -> Option<&mut PartitionInfo> {
+        match self.partition_index.get(&partition_id) {
+            Some(index) => self.available_partitions.get_mut(*index),
+            None => None
         }
     }
 
+    pub fn rebuild_partition_index(&mut self) {
+        self.partition_index = self.available_partitions.iter().enumerate().map(|(index, part)| (part.id, index)).collect();
+    }
+
+    // Dropped columns are not visible here
+    pub fn column(&self, col_index: u32) -> Option<&Column> {
+        if self.is_dropped(col_index) {
+            return None;
+        }
+
+        self.columns.get(col_index as usize)
+    }
+
+    pub fn is_dropped(&self, col_index: u32) -> bool {
+        self.dropped_columns.contains(&col_index)
+    }
+
+    // Columns which were not dropped, with their ids
+    pub fn live_columns(&self) -> Vec<(u32, Column)> {
+        self.columns.iter().enumerate()
+            .filter(|&(index, _)| !self.is_dropped(index as u32))
+            .map(|(index, col)| (index as u32, col.to_owned()))
+            .collect()
+    }
+
+    pub fn column_index(&self, name: &String) -> Option<u32> {
+        self.live_columns().into_iter().find(|&(_, ref col)| col.name == *name).map(|(index, _)| index)
+    }
+
+    pub fn drop_column(&mut self, col_index: u32) {
+        if !self.is_dropped(col_index) {
+            self.dropped_columns.push(col_index);
+        }
+    }
+
+    pub fn rename_column(&mut self, col_index: u32, name: String) {
+        self.columns[col_index as usize].name = name;
+    }
+
     pub fn add_column(&mut self, data_type: BlockType, name: String) -> Column {
         let new_col = Column { data_type: data_type, name: name };
         self.columns.push(new_col.to_owned());
@@ -0,0 +1,263 @@
+use api::{CursorScanRequest, CursorResponse, ScanResultMessage, scan_matching_rows, scanned_partition_info, materialize_rows, check_projection};
+use catalog::PartitionInfo;
+use error;
+use error::Error;
+use manager::Manager;
+use rand;
+use rand::Rng;
+use std::cmp;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Matching rows are found upfront, only the chunks are materialized on demand
+pub struct Cursor {
+    pub id : u64,
+    // Partitions are resolved again on each fetch, as in memory partition might have been dumped meanwhile
+    part_ids : Vec<u64>,
+    rows : Vec<(usize, u32)>,
+    position : usize,
+    chunk_size : usize,
+    projection : Vec<u32>,
+    last_access : Instant
+}
+
+impl Cursor {
+    fn has_more(&self) -> bool {
+        self.position < self.rows.len()
+    }
+
+    fn next_chunk(&mut self, manager : &Manager) -> error::Result<ScanResultMessage> {
+        check_projection(&manager.catalog, &self.projection)?;
+
+        let mut part_infos:Vec<PartitionInfo> = Vec::new();
+        for part_id in &self.part_ids {
+            part_infos.push(scanned_partition_info(manager, *part_id)?);
+        }
+
+        let end = cmp::min(self.position + self.chunk_size, self.rows.len());
+        let chunk = materialize_rows(manager, &part_infos, &self.rows[self.position..end], &self.projection)?;
+
+        self.position = end;
+        self.last_access = Instant::now();
+
+        Ok(chunk)
+    }
+}
+
+pub struct CursorRegistry {
+    cursors : HashMap<u64, Cursor>,
+    // Cursors not accessed for that long are dropped
+    expire_after : Duration,
+    // Each cursor keeps all its matching rows, so the least recently accessed one is dropped when there are more
+    max_open : usize
+}
+
+impl CursorRegistry {
+    pub fn new(expire_after : Duration, max_open : usize) -> CursorRegistry {
+        CursorRegistry {
+            cursors: HashMap::new(),
+            expire_after: expire_after,
+            max_open: max_open
+        }
+    }
+
+    pub fn open(&mut self, manager : &Manager, req : &CursorScanRequest) -> error::Result<CursorResponse> {
+        let (part_infos, rows) = scan_matching_rows(manager, &req.scan)?;
+
+        let mut cursor_id = rand::thread_rng().gen::<u64>();
+        while self.cursors.contains_key(&cursor_id) {
+            cursor_id = rand::thread_rng().gen::<u64>();
+        }
+
+        let cursor = Cursor {
+            id: cursor_id,
+            part_ids: part_infos.iter().map(|part_info| part_info.id).collect(),
+            rows: rows,
+            position: 0,
+            chunk_size: cmp::max(req.chunk_size, 1) as usize,
+            projection: req.scan.projection.to_owned(),
+            last_access: Instant::now()
+        };
+
+        if self.cursors.len() >= self.max_open {
+            let least_recent = self.cursors.values().min_by_key(|cursor| cursor.last_access).map(|cursor| cursor.id);
+            if let Some(least_recent) = least_recent {
+                self.cursors.remove(&least_recent);
+            }
+        }
+
+        self.cursors.insert(cursor_id, cursor);
+        self.fetch_more(manager, cursor_id)
+    }
+
+    pub fn fetch_more(&mut self, manager : &Manager, cursor_id : u64) -> error::Result<CursorResponse> {
+        let (chunk, has_more) = match self.cursors.get_mut(&cursor_id) {
+            Some(cursor) => {
+                let chunk = cursor.next_chunk(manager)?;
+                (chunk, cursor.has_more())
+            },
+            // Unknown, exhausted or expired
+            None => return Err(Error::UnknownCursor(cursor_id))
+        };
+
+        // No need to keep it once everything was returned
+        if !has_more {
+            self.cursors.remove(&cursor_id);
+        }
+
+        Ok(CursorResponse {
+            cursor_id: cursor_id,
+            has_more: has_more,
+            chunk: chunk
+        })
+    }
+
+    pub fn expire(&mut self) {
+        let expire_after = self.expire_after;
+        self.cursors.retain(|_, cursor| cursor.last_access.elapsed() < expire_after);
+    }
+
+    pub fn len(&self) -> usize {
+        self.cursors.len()
+    }
+}
+
+
+#[cfg(test)]
+use api::{ScanRequest, create_test_manager};
+#[cfg(test)]
+use int_blocks::Block;
+
+#[cfg(test)]
+fn cursor_test_request(chunk_size : u32) -> CursorScanRequest {
+    CursorScanRequest {
+        scan: ScanRequest {
+            min_ts: 0,
+            max_ts: u64::max_value(),
+            partition_id: None,
+            projection: vec![0],
+            filters: vec![],
+            filter_expression: None,
+            offset: 0,
+            limit: None,
+            order_by: None
+        },
+        chunk_size: chunk_size
+    }
+}
+
+#[cfg(test)]
+fn chunk_ts(chunk : &ScanResultMessage) -> Vec<u64> {
+    match chunk.blocks.first() {
+        Some(&Block::Int64Dense(ref b)) => b.data.to_owned(),
+        None => vec![],
+        _ => panic!("Expected ts block")
+    }
+}
+
+#[test]
+fn cursor_returns_chunks_until_exhausted() {
+    let manager = create_test_manager("/tmp/hyena_test_cursor_chunks");
+    let mut registry = CursorRegistry::new(Duration::from_secs(60), 10);
+
+    let first = registry.open(&manager, &cursor_test_request(3)).unwrap();
+    assert_eq!(true, first.has_more);
+    assert_eq!(3, first.chunk.row_count);
+
+    let second = registry.fetch_more(&manager, first.cursor_id).unwrap();
+    assert_eq!(true, second.has_more);
+    assert_eq!(3, second.chunk.row_count);
+
+    let third = registry.fetch_more(&manager, first.cursor_id).unwrap();
+    assert_eq!(false, third.has_more);
+    assert_eq!(2, third.chunk.row_count);
+    assert_eq!(0, registry.len());
+
+    let mut all_ts = chunk_ts(&first.chunk);
+    all_ts.extend(chunk_ts(&second.chunk));
+    all_ts.extend(chunk_ts(&third.chunk));
+    let mut expected_ts = vec![];
+    for base_ts in &[1495490000 * 1000000, 1495500000 * 1000000] {
+        expected_ts.extend((0..4).map(|i| base_ts + i * 1000));
+    }
+    assert_eq!(expected_ts, all_ts);
+
+    // Closed cursor
+    assert_eq!(5, registry.fetch_more(&manager, first.cursor_id).unwrap_err().status());
+}
+
+#[test]
+fn cursor_expires() {
+    let manager = create_test_manager("/tmp/hyena_test_cursor_expires");
+
+    let mut registry = CursorRegistry::new(Duration::from_secs(60), 10);
+    registry.open(&manager, &cursor_test_request(1)).unwrap();
+    registry.expire();
+    assert_eq!(1, registry.len());
+
+    let mut registry = CursorRegistry::new(Duration::from_secs(0), 10);
+    let first = registry.open(&manager, &cursor_test_request(1)).unwrap();
+    registry.expire();
+    assert_eq!(0, registry.len());
+    assert!(registry.fetch_more(&manager, first.cursor_id).is_err());
+}
+
+#[test]
+fn cursor_survives_in_mem_partition_dump() {
+    use api::InsertMessage;
+    use catalog::BlockType;
+    use int_blocks::Int64DenseBlock;
+
+    let mut manager = create_test_manager("/tmp/hyena_test_cursor_dump");
+    let last_ts = manager.catalog.available_partitions[1].max_ts;
+
+    manager.insert(&InsertMessage {
+        row_count: 2,
+        col_count: 2,
+        col_types: vec![(0, BlockType::Int64Dense), (1, BlockType::Int64Dense)],
+        blocks: vec![
+            Block::Int64Dense(Int64DenseBlock{ data: vec![last_ts+1000, last_ts+2000] }),
+            Block::Int64Dense(Int64DenseBlock{ data: vec![1, 2] })
+        ]
+    }).unwrap();
+
+    let mut registry = CursorRegistry::new(Duration::from_secs(60), 10);
+    let first = registry.open(&manager, &cursor_test_request(8)).unwrap();
+    assert_eq!(true, first.has_more);
+
+    manager.dump_in_mem_partition().unwrap();
+
+    let second = registry.fetch_more(&manager, first.cursor_id).unwrap();
+    assert_eq!(false, second.has_more);
+    assert_eq!(vec![last_ts+1000, last_ts+2000], chunk_ts(&second.chunk));
+}
+
+#[test]
+fn cursor_checks_projection_on_fetch() {
+    let mut manager = create_test_manager("/tmp/hyena_test_cursor_dropped_column");
+    let mut req = cursor_test_request(1);
+    req.scan.projection = vec![0, 3];
+
+    let mut registry = CursorRegistry::new(Duration::from_secs(60), 10);
+    let first = registry.open(&manager, &req).unwrap();
+
+    manager.drop_column(3).unwrap();
+    manager.purge_dropped_blocks().unwrap();
+    assert_eq!(2, registry.fetch_more(&manager, first.cursor_id).unwrap_err().status());
+}
+
+#[test]
+fn cursor_count_is_bounded() {
+    let manager = create_test_manager("/tmp/hyena_test_cursor_count");
+
+    let mut registry = CursorRegistry::new(Duration::from_secs(60), 2);
+    let first = registry.open(&manager, &cursor_test_request(1)).unwrap();
+    let second = registry.open(&manager, &cursor_test_request(1)).unwrap();
+    registry.fetch_more(&manager, first.cursor_id).unwrap();
+
+    // The least recently fetched one goes
+    registry.open(&manager, &cursor_test_request(1)).unwrap();
+    assert_eq!(2, registry.len());
+    assert_eq!(5, registry.fetch_more(&manager, second.cursor_id).unwrap_err().status());
+    registry.fetch_more(&manager, first.cursor_id).unwrap();
+}
@@ -0,0 +1,92 @@
+use bincode;
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+// Everything that can go wrong while handling a client request. Each kind has its own status code,
+// which is sent back to the client instead of the daemon going down.
+#[derive(Debug)]
+pub enum Error {
+    // The message could not be deserialized or its content is inconsistent
+    BadPayload(String),
+    UnknownColumn(u32),
+    UnknownColumnName(String),
+    TypeMismatch(String),
+    UnknownPartition(u64),
+    UnknownCursor(u64),
+    Io(io::Error)
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+pub const STATUS_OK: u32 = 0;
+
+impl Error {
+    pub fn status(&self) -> u32 {
+        match self {
+            &Error::BadPayload(_) => 1,
+            &Error::UnknownColumn(_) | &Error::UnknownColumnName(_) => 2,
+            &Error::TypeMismatch(_) => 3,
+            &Error::UnknownPartition(_) => 4,
+            &Error::UnknownCursor(_) => 5,
+            &Error::Io(_) => 6
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::BadPayload(ref msg) => write!(f, "Bad payload: {}", msg),
+            &Error::UnknownColumn(col_index) => write!(f, "Unknown column {}", col_index),
+            &Error::UnknownColumnName(ref name) => write!(f, "Unknown column <{}>", name),
+            &Error::TypeMismatch(ref msg) => write!(f, "Type mismatch: {}", msg),
+            &Error::UnknownPartition(partition_id) => write!(f, "Unknown partition {}", partition_id),
+            &Error::UnknownCursor(cursor_id) => write!(f, "Unknown or expired cursor {}", cursor_id),
+            &Error::Io(ref e) => write!(f, "IO failure: {}", e)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            &Error::BadPayload(_) => "bad payload",
+            &Error::UnknownColumn(_) | &Error::UnknownColumnName(_) => "unknown column",
+            &Error::TypeMismatch(_) => "type mismatch",
+            &Error::UnknownPartition(_) => "unknown partition",
+            &Error::UnknownCursor(_) => "unknown cursor",
+            &Error::Io(_) => "IO failure"
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Error {
+        Error::BadPayload(e.to_string())
+    }
+}
+
+
+#[test]
+fn it_maps_errors_to_status() {
+    let errors = vec![
+        Error::BadPayload(String::from("truncated")),
+        Error::UnknownColumn(7),
+        Error::TypeMismatch(String::from("String vs Int64Dense")),
+        Error::UnknownPartition(123),
+        Error::UnknownCursor(456),
+        Error::from(io::Error::new(io::ErrorKind::Other, "disk full"))
+    ];
+
+    let statuses:Vec<u32> = errors.iter().map(|e| e.status()).collect();
+    assert_eq!(vec![1, 2, 3, 4, 5, 6], statuses);
+    assert_eq!("Unknown column 7", errors[1].to_string());
+}
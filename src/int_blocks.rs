@@ -3,7 +3,13 @@ use bincode::{serialize, deserialize, Infinite};
 use api::ScanComparison;
 use scan::BlockScanConsumer;
 use catalog::BlockType;
+use regex;
+use regex::bytes::Regex;
+use roaring::RoaringBitmap;
 use std::cmp;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::mem;
 
 // Sorry for this copypasta, it took me bit more time to make templates work and still had some issues, so consider this just a mock
 
@@ -11,6 +17,11 @@ pub trait Scannable<T> {
     fn scan(&self, op : ScanComparison, val : &T, scan_consumer : &mut BlockScanConsumer);
 }
 
+// In and range filters, which carry a list of values rather than a single one
+pub trait MultiScannable<T> {
+    fn scan_multi(&self, op : ScanComparison, vals : &Vec<T>, scan_consumer : &mut BlockScanConsumer);
+}
+
 pub trait Deletable {
     fn delete(&mut self, offsets : &Vec<u32>);
 }
@@ -24,6 +35,15 @@ pub trait Movable {
     fn move_data(&mut self, target : &mut Block, scan_consumer : &BlockScanConsumer);
 }
 
+// Value of a single record, as visited by Block::for_each_matching
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ScalarValue<'a> {
+    UInt(u64),
+    Float(f64),
+    Int(i64),
+    Str(&'a [u8])
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum Block {
     Int64Dense(Int64DenseBlock),
@@ -31,7 +51,16 @@ pub enum Block {
     Int32Sparse(Int32SparseBlock),
     Int16Sparse(Int16SparseBlock),
     Int8Sparse(Int8SparseBlock),
-    StringBlock(StringBlock)
+    StringBlock(StringBlock),
+    Float64Dense(Float64DenseBlock),
+    Float64Sparse(Float64SparseBlock),
+    Float32Sparse(Float32SparseBlock),
+    SignedInt64Sparse(SignedInt64SparseBlock),
+    SignedInt32Sparse(SignedInt32SparseBlock),
+    SignedInt16Sparse(SignedInt16SparseBlock),
+    SignedInt8Sparse(SignedInt8SparseBlock),
+    Bitmap(BitmapBlock),
+    DictString(DictStringBlock)
 }
 
 impl Block {
@@ -42,7 +71,80 @@ impl Block {
             &BlockType::Int32Sparse => Block::Int32Sparse(Int32SparseBlock { data: Vec::new() }),
             &BlockType::Int16Sparse => Block::Int16Sparse(Int16SparseBlock { data: Vec::new() }),
             &BlockType::Int8Sparse => Block::Int8Sparse(Int8SparseBlock { data: Vec::new() }),
-            &BlockType::String => Block::StringBlock(StringBlock::new())
+            &BlockType::String => Block::StringBlock(StringBlock::new()),
+            &BlockType::Float64Dense => Block::Float64Dense(Float64DenseBlock { data: Vec::new() }),
+            &BlockType::Float64Sparse => Block::Float64Sparse(Float64SparseBlock { data: Vec::new() }),
+            &BlockType::Float32Sparse => Block::Float32Sparse(Float32SparseBlock { data: Vec::new() }),
+            &BlockType::SignedInt64Sparse => Block::SignedInt64Sparse(SignedInt64SparseBlock { data: Vec::new() }),
+            &BlockType::SignedInt32Sparse => Block::SignedInt32Sparse(SignedInt32SparseBlock { data: Vec::new() }),
+            &BlockType::SignedInt16Sparse => Block::SignedInt16Sparse(SignedInt16SparseBlock { data: Vec::new() }),
+            &BlockType::SignedInt8Sparse => Block::SignedInt8Sparse(SignedInt8SparseBlock { data: Vec::new() }),
+            &BlockType::Bitmap => Block::Bitmap(BitmapBlock::new()),
+            &BlockType::DictString => Block::DictString(DictStringBlock::new())
+        }
+    }
+
+    // Rows which are already there get zeros in dense blocks, so the next rows are appended at the right offsets
+    pub fn create_block_for_rows(block_type: &BlockType, row_count: usize) -> Block {
+        match block_type {
+            &BlockType::Int64Dense => Block::Int64Dense(Int64DenseBlock { data: vec![0; row_count] }),
+            &BlockType::Float64Dense => Block::Float64Dense(Float64DenseBlock { data: vec![0.0; row_count] }),
+            _ => Block::create_block(block_type)
+        }
+    }
+
+    pub fn data_type(&self) -> BlockType {
+        match self {
+            &Block::Int64Dense(_) => BlockType::Int64Dense,
+            &Block::Int64Sparse(_) => BlockType::Int64Sparse,
+            &Block::Int32Sparse(_) => BlockType::Int32Sparse,
+            &Block::Int16Sparse(_) => BlockType::Int16Sparse,
+            &Block::Int8Sparse(_) => BlockType::Int8Sparse,
+            &Block::StringBlock(_) => BlockType::String,
+            &Block::Float64Dense(_) => BlockType::Float64Dense,
+            &Block::Float64Sparse(_) => BlockType::Float64Sparse,
+            &Block::Float32Sparse(_) => BlockType::Float32Sparse,
+            &Block::SignedInt64Sparse(_) => BlockType::SignedInt64Sparse,
+            &Block::SignedInt32Sparse(_) => BlockType::SignedInt32Sparse,
+            &Block::SignedInt16Sparse(_) => BlockType::SignedInt16Sparse,
+            &Block::SignedInt8Sparse(_) => BlockType::SignedInt8Sparse,
+            &Block::Bitmap(_) => BlockType::Bitmap,
+            &Block::DictString(_) => BlockType::DictString
+        }
+    }
+
+    // Copy of the block converted to a wider integer type. Conversion to dense is possible only when
+    // the block has a value for each of row_count rows.
+    pub fn widen(&self, target : &BlockType, row_count : usize) -> Option<Block> {
+        if !self.data_type().can_widen_to(target) {
+            return None;
+        }
+
+        if let &Block::StringBlock(ref b) = self {
+            return Some(Block::DictString(DictStringBlock::from_string_block(b)));
+        }
+
+        let values:Vec<(u32, u64)> = match self {
+            &Block::Int64Sparse(ref b) => b.data.to_owned(),
+            &Block::Int32Sparse(ref b) => b.data.iter().map(|&(o, v)| (o, v as u64)).collect(),
+            &Block::Int16Sparse(ref b) => b.data.iter().map(|&(o, v)| (o, v as u64)).collect(),
+            &Block::Int8Sparse(ref b) => b.data.iter().map(|&(o, v)| (o, v as u64)).collect(),
+            _ => return None
+        };
+
+        match target {
+            &BlockType::Int64Dense => {
+                let fully_populated = values.len() == row_count && values.iter().enumerate().all(|(i, &(o, _))| o as usize == i);
+                if !fully_populated {
+                    return None;
+                }
+
+                Some(Block::Int64Dense(Int64DenseBlock { data: values.into_iter().map(|(_, v)| v).collect() }))
+            },
+            &BlockType::Int64Sparse => Some(Block::Int64Sparse(Int64SparseBlock { data: values })),
+            &BlockType::Int32Sparse => Some(Block::Int32Sparse(Int32SparseBlock { data: values.into_iter().map(|(o, v)| (o, v as u32)).collect() })),
+            &BlockType::Int16Sparse => Some(Block::Int16Sparse(Int16SparseBlock { data: values.into_iter().map(|(o, v)| (o, v as u16)).collect() })),
+            _ => None
         }
     }
 
@@ -53,7 +155,137 @@ impl Block {
             &Block::Int32Sparse(ref b) => b.data.len(),
             &Block::Int16Sparse(ref b) => b.data.len(),
             &Block::Int8Sparse(ref b) => b.data.len(),
-            &Block::StringBlock(ref b) => b.index_data.len()
+            &Block::StringBlock(ref b) => b.index_data.len(),
+            &Block::Float64Dense(ref b) => b.data.len(),
+            &Block::Float64Sparse(ref b) => b.data.len(),
+            &Block::Float32Sparse(ref b) => b.data.len(),
+            &Block::SignedInt64Sparse(ref b) => b.data.len(),
+            &Block::SignedInt32Sparse(ref b) => b.data.len(),
+            &Block::SignedInt16Sparse(ref b) => b.data.len(),
+            &Block::SignedInt8Sparse(ref b) => b.data.len(),
+            // Number of rows set
+            &Block::Bitmap(ref b) => b.data.len() as usize,
+            &Block::DictString(ref b) => b.codes.data.len()
+        }
+    }
+
+    // Offsets which have a value (i.e. are not null). Dense blocks and bitmaps have one in every row.
+    pub fn present_offsets(&self, row_count : u32) -> Vec<u32> {
+        match self {
+            &Block::Int64Dense(_) | &Block::Float64Dense(_) | &Block::Bitmap(_) => (0..row_count).collect(),
+            &Block::Int64Sparse(ref b) => b.data.iter().map(|pair| pair.0).collect(),
+            &Block::Int32Sparse(ref b) => b.data.iter().map(|pair| pair.0).collect(),
+            &Block::Int16Sparse(ref b) => b.data.iter().map(|pair| pair.0).collect(),
+            &Block::Int8Sparse(ref b) => b.data.iter().map(|pair| pair.0).collect(),
+            &Block::StringBlock(ref b) => b.index_data.iter().map(|pair| pair.0).collect(),
+            &Block::Float64Sparse(ref b) => b.data.iter().map(|pair| pair.0).collect(),
+            &Block::Float32Sparse(ref b) => b.data.iter().map(|pair| pair.0).collect(),
+            &Block::SignedInt64Sparse(ref b) => b.data.iter().map(|pair| pair.0).collect(),
+            &Block::SignedInt32Sparse(ref b) => b.data.iter().map(|pair| pair.0).collect(),
+            &Block::SignedInt16Sparse(ref b) => b.data.iter().map(|pair| pair.0).collect(),
+            &Block::SignedInt8Sparse(ref b) => b.data.iter().map(|pair| pair.0).collect(),
+            &Block::DictString(ref b) => b.codes.data.iter().map(|pair| pair.0).collect()
+        }
+    }
+
+    // Appends all records of the other block with offsets moved by base_offset (for dense blocks it is implied)
+    pub fn append_block(&mut self, other : &Block, base_offset : u32) {
+        match self {
+            &mut Block::Int64Dense(ref mut b) => match other {
+                &Block::Int64Dense(ref c) => b.data.extend(&c.data),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::Int64Sparse(ref mut b) => match other {
+                &Block::Int64Sparse(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::Int32Sparse(ref mut b) => match other {
+                &Block::Int32Sparse(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::Int16Sparse(ref mut b) => match other {
+                &Block::Int16Sparse(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::Int8Sparse(ref mut b) => match other {
+                &Block::Int8Sparse(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::StringBlock(ref mut b) => match other {
+                &Block::StringBlock(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::Float64Dense(ref mut b) => match other {
+                &Block::Float64Dense(ref c) => b.data.extend(&c.data),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::Float64Sparse(ref mut b) => match other {
+                &Block::Float64Sparse(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::Float32Sparse(ref mut b) => match other {
+                &Block::Float32Sparse(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::SignedInt64Sparse(ref mut b) => match other {
+                &Block::SignedInt64Sparse(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::SignedInt32Sparse(ref mut b) => match other {
+                &Block::SignedInt32Sparse(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::SignedInt16Sparse(ref mut b) => match other {
+                &Block::SignedInt16Sparse(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::SignedInt8Sparse(ref mut b) => match other {
+                &Block::SignedInt8Sparse(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::Bitmap(ref mut b) => match other {
+                &Block::Bitmap(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            },
+            &mut Block::DictString(ref mut b) => match other {
+                &Block::DictString(ref c) => b.append_block(c, base_offset),
+                _ => panic!("Non matching blocks")
+            }
+        }
+    }
+
+    // Visits values of all matching offsets which are set, along with the position of the offset in the consumer.
+    // Unlike consume(), nothing is copied.
+    pub fn for_each_matching<F>(&self, scan_consumer : &BlockScanConsumer, mut f : F) where F : FnMut(usize, ScalarValue) {
+        match self {
+            &Block::Int64Dense(ref b) => {
+                for (position, index) in scan_consumer.matching_offsets.iter().enumerate() {
+                    f(position, ScalarValue::UInt(b.data[*index as usize]));
+                }
+            },
+            &Block::Int64Sparse(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::UInt(*v))),
+            &Block::Int32Sparse(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::UInt(*v as u64))),
+            &Block::Int16Sparse(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::UInt(*v as u64))),
+            &Block::Int8Sparse(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::UInt(*v as u64))),
+            &Block::StringBlock(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::Str(v))),
+            &Block::Float64Dense(ref b) => {
+                for (position, index) in scan_consumer.matching_offsets.iter().enumerate() {
+                    f(position, ScalarValue::Float(b.data[*index as usize]));
+                }
+            },
+            &Block::Float64Sparse(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::Float(*v))),
+            &Block::Float32Sparse(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::Float(*v as f64))),
+            &Block::SignedInt64Sparse(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::Int(*v as i64))),
+            &Block::SignedInt32Sparse(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::Int(*v as i64))),
+            &Block::SignedInt16Sparse(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::Int(*v as i64))),
+            &Block::SignedInt8Sparse(ref b) => b.for_each_matching(scan_consumer, |position, v| f(position, ScalarValue::Int(*v as i64))),
+            // Unlike sparse blocks, each row has a value here
+            &Block::Bitmap(ref b) => {
+                for (position, offset) in scan_consumer.matching_offsets.iter().enumerate() {
+                    f(position, ScalarValue::UInt(b.data.contains(*offset) as u64));
+                }
+            },
+            &Block::DictString(ref b) => b.codes.for_each_matching(scan_consumer, |position, code| f(position, ScalarValue::Str(b.value(*code))))
         }
     }
 
@@ -84,7 +316,33 @@ impl Block {
             &Block::StringBlock(ref b) => {
                 output_block = Block::StringBlock(b.filter_scan_results(scan_consumer))
             },
-            _ => panic!("Unrecognized block type")
+            &Block::Float64Dense(ref b) => {
+                output_block = Block::Float64Dense(b.filter_scan_results(scan_consumer));
+            },
+            &Block::Float64Sparse(ref b) => {
+                output_block = Block::Float64Sparse(b.filter_scan_results(scan_consumer));
+            },
+            &Block::Float32Sparse(ref b) => {
+                output_block = Block::Float32Sparse(b.filter_scan_results(scan_consumer));
+            },
+            &Block::SignedInt64Sparse(ref b) => {
+                output_block = Block::SignedInt64Sparse(b.filter_scan_results(scan_consumer));
+            },
+            &Block::SignedInt32Sparse(ref b) => {
+                output_block = Block::SignedInt32Sparse(b.filter_scan_results(scan_consumer));
+            },
+            &Block::SignedInt16Sparse(ref b) => {
+                output_block = Block::SignedInt16Sparse(b.filter_scan_results(scan_consumer));
+            },
+            &Block::SignedInt8Sparse(ref b) => {
+                output_block = Block::SignedInt8Sparse(b.filter_scan_results(scan_consumer));
+            },
+            &Block::Bitmap(ref b) => {
+                output_block = Block::Bitmap(b.filter_scan_results(scan_consumer));
+            },
+            &Block::DictString(ref b) => {
+                output_block = Block::DictString(b.filter_scan_results(scan_consumer));
+            }
         }
 
         output_block
@@ -99,6 +357,14 @@ impl Deletable for Block {
             &mut Block::Int32Sparse(ref mut b) => b.delete(offsets),
             &mut Block::Int16Sparse(ref mut b) => b.delete(offsets),
             &mut Block::Int8Sparse(ref mut b) => b.delete(offsets),
+            &mut Block::Float64Sparse(ref mut b) => b.delete(offsets),
+            &mut Block::Float32Sparse(ref mut b) => b.delete(offsets),
+            &mut Block::SignedInt64Sparse(ref mut b) => b.delete(offsets),
+            &mut Block::SignedInt32Sparse(ref mut b) => b.delete(offsets),
+            &mut Block::SignedInt16Sparse(ref mut b) => b.delete(offsets),
+            &mut Block::SignedInt8Sparse(ref mut b) => b.delete(offsets),
+            &mut Block::Bitmap(ref mut b) => b.delete(offsets),
+            &mut Block::DictString(ref mut b) => b.delete(offsets),
             _ => panic!("I don't know how to handle such block type")
         }
     }
@@ -127,6 +393,38 @@ impl Movable for Block {
                 &mut Block::Int8Sparse(ref mut c) => b.move_data(c, scan_consumer),
                 _ => panic!("Not matching block types")
             },
+            &mut Block::Float64Sparse(ref mut b) => match target {
+                &mut Block::Float64Sparse(ref mut c) => b.move_data(c, scan_consumer),
+                _ => panic!("Not matching block types")
+            },
+            &mut Block::Float32Sparse(ref mut b) => match target {
+                &mut Block::Float32Sparse(ref mut c) => b.move_data(c, scan_consumer),
+                _ => panic!("Not matching block types")
+            },
+            &mut Block::SignedInt64Sparse(ref mut b) => match target {
+                &mut Block::SignedInt64Sparse(ref mut c) => b.move_data(c, scan_consumer),
+                _ => panic!("Not matching block types")
+            },
+            &mut Block::SignedInt32Sparse(ref mut b) => match target {
+                &mut Block::SignedInt32Sparse(ref mut c) => b.move_data(c, scan_consumer),
+                _ => panic!("Not matching block types")
+            },
+            &mut Block::SignedInt16Sparse(ref mut b) => match target {
+                &mut Block::SignedInt16Sparse(ref mut c) => b.move_data(c, scan_consumer),
+                _ => panic!("Not matching block types")
+            },
+            &mut Block::SignedInt8Sparse(ref mut b) => match target {
+                &mut Block::SignedInt8Sparse(ref mut c) => b.move_data(c, scan_consumer),
+                _ => panic!("Not matching block types")
+            },
+            &mut Block::Bitmap(ref mut b) => match target {
+                &mut Block::Bitmap(ref mut c) => b.move_data(c, scan_consumer),
+                _ => panic!("Not matching block types")
+            },
+            &mut Block::DictString(ref mut b) => match target {
+                &mut Block::DictString(ref mut c) => b.move_data(c, scan_consumer),
+                _ => panic!("Not matching block types")
+            },
             _ => panic!("I don't know how to handle such block type")
         }
     }
@@ -136,6 +434,7 @@ impl Scannable<String> for Block {
     fn scan(&self, op: ScanComparison, val: &String, scan_consumer: &mut BlockScanConsumer) {
         match self {
             &Block::StringBlock(ref b) => b.scan(op, val, scan_consumer),
+            &Block::DictString(ref b) => b.scan(op, val, scan_consumer),
             _ => panic!("Wrong block type for String scan")
         }
     }
@@ -145,6 +444,7 @@ impl Upsertable<String> for Block {
     fn multi_upsert(&mut self, offsets : &Vec<u32>, val : &String) {
         match self {
             &mut Block::StringBlock(ref mut b) => b.multi_upsert(offsets, val.as_bytes()),
+            &mut Block::DictString(ref mut b) => b.multi_upsert(offsets, val.as_bytes()),
             _ => panic!("Wrong block type for String scan")
         }
     }
@@ -155,6 +455,10 @@ impl Upsertable<String> for Block {
                 &Block::StringBlock(ref c) => b.upsert(c),
                 _ => panic!("Wrong block type")
             },
+            &mut Block::DictString(ref mut b) => match data {
+                &Block::DictString(ref c) => b.upsert(c),
+                _ => panic!("Wrong block type")
+            },
             _ => panic!("Wrong block type")
         }
     }
@@ -165,9 +469,15 @@ impl Scannable<u64> for Block {
         match self {
             &Block::Int64Dense(ref b) => b.scan(op, val, scan_consumer),
             &Block::Int64Sparse(ref b) => b.scan(op, val, scan_consumer),
-            &Block::Int32Sparse(ref b) => b.scan(op, &(*val as u32), scan_consumer),
-            &Block::Int16Sparse(ref b) => b.scan(op, &(*val as u16), scan_consumer),
-            &Block::Int8Sparse(ref b) => b.scan(op, &(*val as u8), scan_consumer),
+            &Block::Int32Sparse(ref b) => if let Some((op, narrow)) = narrow_value(&op, *val as i128, 0, u32::max_value() as i128, |v| v as u32) {
+                b.scan(op, &narrow, scan_consumer)
+            },
+            &Block::Int16Sparse(ref b) => if let Some((op, narrow)) = narrow_value(&op, *val as i128, 0, u16::max_value() as i128, |v| v as u16) {
+                b.scan(op, &narrow, scan_consumer)
+            },
+            &Block::Int8Sparse(ref b) => if let Some((op, narrow)) = narrow_value(&op, *val as i128, 0, u8::max_value() as i128, |v| v as u8) {
+                b.scan(op, &narrow, scan_consumer)
+            },
             _ => panic!("Unrecognized u64 block type")
         }
     }
@@ -276,6 +586,272 @@ impl Upsertable<u8> for Block {
     }
 }
 
+// Floats are passed in ScanFilter.val as f64 bits
+impl Scannable<f64> for Block {
+    fn scan(&self, op : ScanComparison, val : &f64, scan_consumer : &mut BlockScanConsumer) {
+        match self {
+            &Block::Float64Dense(ref b) => b.scan(op, val, scan_consumer),
+            &Block::Float64Sparse(ref b) => b.scan(op, val, scan_consumer),
+            &Block::Float32Sparse(ref b) => b.scan(op, &(*val as f32), scan_consumer),
+            _ => panic!("Unrecognized f64 block type")
+        }
+    }
+}
+
+impl Upsertable<f64> for Block {
+    fn multi_upsert(&mut self, offsets : &Vec<u32>, val : &f64) {
+        match self {
+            &mut Block::Float64Sparse(ref mut b) => b.multi_upsert(offsets, *val),
+            _ => panic!("Wrong block type")
+        }
+    }
+
+    fn upsert(&mut self, data : &Block) {
+        match self {
+            &mut Block::Float64Sparse(ref mut b) => match data {
+                &Block::Float64Sparse(ref c) => b.upsert(c),
+                _ => panic!("Wrong block")
+            },
+            _ => panic!("Wrong block type")
+        }
+    }
+}
+
+impl Upsertable<f32> for Block {
+    fn multi_upsert(&mut self, offsets : &Vec<u32>, val : &f32) {
+        match self {
+            &mut Block::Float32Sparse(ref mut b) => b.multi_upsert(offsets, *val),
+            _ => panic!("Wrong block type")
+        }
+    }
+
+    fn upsert(&mut self, data : &Block) {
+        match self {
+            &mut Block::Float32Sparse(ref mut b) => match data {
+                &Block::Float32Sparse(ref c) => b.upsert(c),
+                _ => panic!("Wrong block")
+            },
+            _ => panic!("Wrong block type")
+        }
+    }
+}
+
+// Signed values are passed in ScanFilter.val as two's complement
+impl Scannable<i64> for Block {
+    fn scan(&self, op : ScanComparison, val : &i64, scan_consumer : &mut BlockScanConsumer) {
+        match self {
+            &Block::SignedInt64Sparse(ref b) => b.scan(op, val, scan_consumer),
+            &Block::SignedInt32Sparse(ref b) => if let Some((op, narrow)) = narrow_value(&op, *val as i128, i32::min_value() as i128, i32::max_value() as i128, |v| v as i32) {
+                b.scan(op, &narrow, scan_consumer)
+            },
+            &Block::SignedInt16Sparse(ref b) => if let Some((op, narrow)) = narrow_value(&op, *val as i128, i16::min_value() as i128, i16::max_value() as i128, |v| v as i16) {
+                b.scan(op, &narrow, scan_consumer)
+            },
+            &Block::SignedInt8Sparse(ref b) => if let Some((op, narrow)) = narrow_value(&op, *val as i128, i8::min_value() as i128, i8::max_value() as i128, |v| v as i8) {
+                b.scan(op, &narrow, scan_consumer)
+            },
+            _ => panic!("Unrecognized i64 block type")
+        }
+    }
+}
+
+impl MultiScannable<u64> for Block {
+    fn scan_multi(&self, op : ScanComparison, vals : &Vec<u64>, scan_consumer : &mut BlockScanConsumer) {
+        let wide:Vec<i128> = vals.iter().map(|v| *v as i128).collect();
+
+        match self {
+            &Block::Int64Dense(ref b) => b.scan_multi(op, vals, scan_consumer),
+            &Block::Int64Sparse(ref b) => b.scan_multi(op, vals, scan_consumer),
+            &Block::Int32Sparse(ref b) => {
+                let (op, narrow) = narrow_values(&op, &wide, 0, u32::max_value() as i128, |v| v as u32);
+                b.scan_multi(op, &narrow, scan_consumer)
+            },
+            &Block::Int16Sparse(ref b) => {
+                let (op, narrow) = narrow_values(&op, &wide, 0, u16::max_value() as i128, |v| v as u16);
+                b.scan_multi(op, &narrow, scan_consumer)
+            },
+            &Block::Int8Sparse(ref b) => {
+                let (op, narrow) = narrow_values(&op, &wide, 0, u8::max_value() as i128, |v| v as u8);
+                b.scan_multi(op, &narrow, scan_consumer)
+            },
+            _ => panic!("Unrecognized u64 block type")
+        }
+    }
+}
+
+impl MultiScannable<i64> for Block {
+    fn scan_multi(&self, op : ScanComparison, vals : &Vec<i64>, scan_consumer : &mut BlockScanConsumer) {
+        let wide:Vec<i128> = vals.iter().map(|v| *v as i128).collect();
+
+        match self {
+            &Block::SignedInt64Sparse(ref b) => b.scan_multi(op, vals, scan_consumer),
+            &Block::SignedInt32Sparse(ref b) => {
+                let (op, narrow) = narrow_values(&op, &wide, i32::min_value() as i128, i32::max_value() as i128, |v| v as i32);
+                b.scan_multi(op, &narrow, scan_consumer)
+            },
+            &Block::SignedInt16Sparse(ref b) => {
+                let (op, narrow) = narrow_values(&op, &wide, i16::min_value() as i128, i16::max_value() as i128, |v| v as i16);
+                b.scan_multi(op, &narrow, scan_consumer)
+            },
+            &Block::SignedInt8Sparse(ref b) => {
+                let (op, narrow) = narrow_values(&op, &wide, i8::min_value() as i128, i8::max_value() as i128, |v| v as i8);
+                b.scan_multi(op, &narrow, scan_consumer)
+            },
+            _ => panic!("Unrecognized i64 block type")
+        }
+    }
+}
+
+impl MultiScannable<f64> for Block {
+    fn scan_multi(&self, op : ScanComparison, vals : &Vec<f64>, scan_consumer : &mut BlockScanConsumer) {
+        match self {
+            &Block::Float64Dense(ref b) => b.scan_multi(op, vals, scan_consumer),
+            &Block::Float64Sparse(ref b) => b.scan_multi(op, vals, scan_consumer),
+            &Block::Float32Sparse(ref b) => b.scan_multi(op, &vals.iter().map(|v| *v as f32).collect(), scan_consumer),
+            _ => panic!("Unrecognized f64 block type")
+        }
+    }
+}
+
+impl MultiScannable<Vec<u8>> for Block {
+    fn scan_multi(&self, op : ScanComparison, vals : &Vec<Vec<u8>>, scan_consumer : &mut BlockScanConsumer) {
+        match self {
+            &Block::StringBlock(ref b) => b.scan_multi(op, vals, scan_consumer),
+            &Block::DictString(ref b) => b.scan_multi(op, vals, scan_consumer),
+            _ => panic!("Wrong block type for String scan")
+        }
+    }
+}
+
+// Converts filter values to a narrower type. Values out of its range can't be in the list, while range bounds are capped.
+fn narrow_values<T, F : Fn(i128) -> T>(op : &ScanComparison, vals : &Vec<i128>, min : i128, max : i128, convert : F) -> (ScanComparison, Vec<T>) {
+    if *op == ScanComparison::In || vals.len() != 2 {
+        return (op.to_owned(), vals.iter().filter(|v| **v >= min && **v <= max).map(|v| convert(*v)).collect());
+    }
+
+    let (low, high) = (vals[0], vals[1]);
+    let out_of_range = low > max || high < min || (*op == ScanComparison::BetweenHalfOpen && high <= min);
+    if out_of_range {
+        // Nothing can match
+        return (ScanComparison::In, Vec::new());
+    }
+
+    // Upper bound beyond the range includes the max value
+    let op = if high > max { ScanComparison::Between } else { op.to_owned() };
+    (op, vec![convert(cmp::max(low, min)), convert(cmp::min(high, max))])
+}
+
+// Converts a single filter value to a narrower type, the same way as zone maps compare it. None when no value of the type can match.
+fn narrow_value<T, F : Fn(i128) -> T>(op : &ScanComparison, val : i128, min : i128, max : i128, convert : F) -> Option<(ScanComparison, T)> {
+    if val >= min && val <= max {
+        return Some((op.to_owned(), convert(val)));
+    }
+
+    // Every value of the type is on the same side of the filter value
+    let above_all = val > max;
+    match op {
+        &ScanComparison::Lt | &ScanComparison::LtEq if above_all => Some((ScanComparison::GtEq, convert(min))),
+        &ScanComparison::Gt | &ScanComparison::GtEq if !above_all => Some((ScanComparison::GtEq, convert(min))),
+        &ScanComparison::NotEq => Some((ScanComparison::GtEq, convert(min))),
+        _ => None
+    }
+}
+
+impl Upsertable<i64> for Block {
+    fn multi_upsert(&mut self, offsets : &Vec<u32>, val : &i64) {
+        match self {
+            &mut Block::SignedInt64Sparse(ref mut b) => b.multi_upsert(offsets, *val),
+            _ => panic!("Wrong block type")
+        }
+    }
+
+    fn upsert(&mut self, data : &Block) {
+        match self {
+            &mut Block::SignedInt64Sparse(ref mut b) => match data {
+                &Block::SignedInt64Sparse(ref c) => b.upsert(c),
+                _ => panic!("Wrong block")
+            },
+            _ => panic!("Wrong block type")
+        }
+    }
+}
+
+impl Upsertable<i32> for Block {
+    fn multi_upsert(&mut self, offsets : &Vec<u32>, val : &i32) {
+        match self {
+            &mut Block::SignedInt32Sparse(ref mut b) => b.multi_upsert(offsets, *val),
+            _ => panic!("Wrong block type")
+        }
+    }
+
+    fn upsert(&mut self, data : &Block) {
+        match self {
+            &mut Block::SignedInt32Sparse(ref mut b) => match data {
+                &Block::SignedInt32Sparse(ref c) => b.upsert(c),
+                _ => panic!("Wrong block")
+            },
+            _ => panic!("Wrong block type")
+        }
+    }
+}
+
+impl Upsertable<i16> for Block {
+    fn multi_upsert(&mut self, offsets : &Vec<u32>, val : &i16) {
+        match self {
+            &mut Block::SignedInt16Sparse(ref mut b) => b.multi_upsert(offsets, *val),
+            _ => panic!("Wrong block type")
+        }
+    }
+
+    fn upsert(&mut self, data : &Block) {
+        match self {
+            &mut Block::SignedInt16Sparse(ref mut b) => match data {
+                &Block::SignedInt16Sparse(ref c) => b.upsert(c),
+                _ => panic!("Wrong block")
+            },
+            _ => panic!("Wrong block type")
+        }
+    }
+}
+
+impl Upsertable<i8> for Block {
+    fn multi_upsert(&mut self, offsets : &Vec<u32>, val : &i8) {
+        match self {
+            &mut Block::SignedInt8Sparse(ref mut b) => b.multi_upsert(offsets, *val),
+            _ => panic!("Wrong block type")
+        }
+    }
+
+    fn upsert(&mut self, data : &Block) {
+        match self {
+            &mut Block::SignedInt8Sparse(ref mut b) => match data {
+                &Block::SignedInt8Sparse(ref c) => b.upsert(c),
+                _ => panic!("Wrong block")
+            },
+            _ => panic!("Wrong block type")
+        }
+    }
+}
+
+impl Upsertable<bool> for Block {
+    fn multi_upsert(&mut self, offsets : &Vec<u32>, val : &bool) {
+        match self {
+            &mut Block::Bitmap(ref mut b) => b.multi_upsert(offsets, *val),
+            _ => panic!("Wrong block type")
+        }
+    }
+
+    fn upsert(&mut self, data : &Block) {
+        match self {
+            &mut Block::Bitmap(ref mut b) => match data {
+                &Block::Bitmap(ref c) => b.upsert(c),
+                _ => panic!("Wrong block")
+            },
+            _ => panic!("Wrong block type")
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Int64DenseBlock {
     pub data : Vec<u64>
@@ -305,6 +881,103 @@ impl Int64DenseBlock {
     }
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Float64DenseBlock {
+    pub data : Vec<f64>
+}
+
+impl Float64DenseBlock {
+    pub fn new() -> Float64DenseBlock {
+        Float64DenseBlock { data: Vec::new() }
+    }
+
+    pub fn encapsulate_in_block(self) -> Block {
+        Block::Float64Dense(self)
+    }
+
+    pub fn filter_scan_results(&self, scan_consumer : &BlockScanConsumer) -> Float64DenseBlock {
+        Float64DenseBlock { data: scan_consumer.matching_offsets.iter().map(|index| self.data[*index as usize]).collect() }
+    }
+}
+
+// Flags, only the offsets of rows which are set (true) are kept. Rows not set are false rather than empty.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct BitmapBlock {
+    pub data : RoaringBitmap
+}
+
+impl BitmapBlock {
+    pub fn new() -> BitmapBlock {
+        BitmapBlock { data: RoaringBitmap::new() }
+    }
+
+    pub fn encapsulate_in_block(self) -> Block {
+        Block::Bitmap(self)
+    }
+
+    // As false values are not stored, the partition row count is needed to find them
+    pub fn scan(&self, op : ScanComparison, val : bool, row_count : u32, scan_consumer : &mut BlockScanConsumer) {
+        self.scan_matching(value_matches(&true, &op, &val), value_matches(&false, &op, &val), row_count, scan_consumer);
+    }
+
+    pub fn scan_multi(&self, op : ScanComparison, vals : &Vec<bool>, row_count : u32, scan_consumer : &mut BlockScanConsumer) {
+        self.scan_matching(values_match(&true, &op, vals), values_match(&false, &op, vals), row_count, scan_consumer);
+    }
+
+    fn scan_matching(&self, set_matches : bool, unset_matches : bool, row_count : u32, scan_consumer : &mut BlockScanConsumer) {
+        if set_matches && unset_matches {
+            scan_consumer.matching_offsets.extend(0..row_count);
+        } else if set_matches {
+            scan_consumer.matching_offsets.extend(self.data.iter().take_while(|offset| *offset < row_count));
+        } else if unset_matches {
+            scan_consumer.matching_offsets.extend((0..row_count).filter(|offset| !self.data.contains(*offset)));
+        }
+    }
+
+    pub fn filter_scan_results(&self, scan_consumer : &BlockScanConsumer) -> BitmapBlock {
+        let mut block = BitmapBlock::new();
+        for (position, offset) in scan_consumer.matching_offsets.iter().enumerate() {
+            if self.data.contains(*offset) {
+                block.data.insert(position as u32);
+            }
+        }
+        block
+    }
+
+    pub fn append_block(&mut self, other : &BitmapBlock, base_offset : u32) {
+        self.data.extend(other.data.iter().map(|offset| offset + base_offset));
+    }
+
+    pub fn delete(&mut self, offsets : &Vec<u32>) {
+        for offset in offsets {
+            self.data.remove(*offset);
+        }
+    }
+
+    pub fn move_data(&mut self, target : &mut BitmapBlock, scan_consumer : &BlockScanConsumer) {
+        for offset in &scan_consumer.matching_offsets {
+            if self.data.remove(*offset) {
+                target.data.insert(*offset);
+            }
+        }
+    }
+
+    pub fn multi_upsert(&mut self, offsets : &Vec<u32>, v : bool) {
+        for offset in offsets {
+            if v {
+                self.data.insert(*offset);
+            } else {
+                self.data.remove(*offset);
+            }
+        }
+    }
+
+    // Only the set rows are carried by the bitmap, so they are the ones being upserted
+    pub fn upsert(&mut self, data : &BitmapBlock) {
+        self.data |= &data.data;
+    }
+}
+
 // As of now this is byte array essentially
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct StringBlock {
@@ -318,6 +991,18 @@ impl StringBlock {
         StringBlock{ index_data: Vec::new(), str_data: Vec::new() }
     }
 
+    // Each string spans from its position to the position of the next one (or the end of data)
+    pub fn for_each_value<'a, F>(&'a self, mut f : F) where F : FnMut(u32, &'a [u8]) {
+        for (index, &(offset, start_position)) in self.index_data.iter().enumerate() {
+            let end_position = match self.index_data.get(index+1) {
+                Some(&(_, position)) => position,
+                None => self.str_data.len()
+            };
+
+            f(offset, &self.str_data[start_position..end_position]);
+        }
+    }
+
     pub fn delete(&mut self, offsets: &Vec<u32>) {
         // Because the structure is bit more complex here, lets just be naive and rewrite the str_data while updating index data?
 
@@ -459,68 +1144,293 @@ impl StringBlock {
                 new_index_data.push((cur_index.0, last_str_index));
                 new_str_data.extend_from_slice(cur_str_data);
 
-                data_index += 1;
-            }
+                data_index += 1;
+            }
+        }
+
+        self.index_data = new_index_data;
+        self.str_data = new_str_data;
+    }
+
+    pub fn transpose_offsets(&mut self, new_offsets : &Vec<u32>) {
+        for i in 0..new_offsets.len() {
+            let record = &mut self.index_data[i];
+            record.0 = new_offsets[i];
+        }
+    }
+
+    pub fn move_data(&mut self, target : &mut StringBlock, scan_consumer : &BlockScanConsumer) {
+        let mut temp_block = self.filter_scan_results(scan_consumer);
+        temp_block.transpose_offsets(&scan_consumer.matching_offsets);
+
+        target.upsert(&temp_block);
+        self.delete(&scan_consumer.matching_offsets);
+    }
+
+    pub fn append(&mut self, o: u32, v: &[u8]) {
+        let last_index = self.str_data.len();
+        let str_bytes = v;
+        self.index_data.push((o, last_index));
+        self.str_data.extend_from_slice(str_bytes);
+    }
+
+    pub fn append_block(&mut self, other : &StringBlock, base_offset : u32) {
+        let base_position = self.str_data.len();
+        for &(offset, position) in &other.index_data {
+            self.index_data.push((offset + base_offset, position + base_position));
+        }
+        self.str_data.extend_from_slice(&other.str_data);
+    }
+
+    pub fn for_each_matching<F>(&self, scan_consumer : &BlockScanConsumer, mut f : F) where F : FnMut(usize, &[u8]) {
+        let mut block_data_index = 0 as usize;
+
+        for (scan_data_index, target_offset) in scan_consumer.matching_offsets.iter().enumerate() {
+            while block_data_index < self.index_data.len() && self.index_data[block_data_index].0 < *target_offset {
+                block_data_index += 1;
+            }
+
+            if block_data_index == self.index_data.len() {
+                break;
+            }
+
+            if self.index_data[block_data_index].0 == *target_offset {
+                let arr_start_position = self.index_data[block_data_index].1;
+                let arr_end_position = if block_data_index < self.index_data.len()-1 {
+                    self.index_data[block_data_index+1].1
+                } else {
+                    self.str_data.len()
+                };
+
+                f(scan_data_index, &self.str_data[arr_start_position..arr_end_position]);
+                block_data_index += 1;
+            }
+        }
+    }
+
+    pub fn filter_scan_results(&self, scan_consumer: &BlockScanConsumer) -> StringBlock {
+        let mut out_block = StringBlock::new();
+
+        if !scan_consumer.is_ascending() {
+            // E.g. when reordering the rows, each one needs to be looked up separately then
+            for (scan_data_index, target_offset) in scan_consumer.matching_offsets.iter().enumerate() {
+                if let Ok(block_data_index) = self.index_data.binary_search_by_key(target_offset, |&(o, _)| o) {
+                    let arr_end_position = if block_data_index < self.index_data.len()-1 {
+                        self.index_data[block_data_index+1].1
+                    } else {
+                        self.str_data.len()
+                    };
+
+                    out_block.append(scan_data_index as u32, &self.str_data[self.index_data[block_data_index].1..arr_end_position]);
+                }
+            }
+            return out_block;
+        }
+
+        // TODO: binary search-like operations would be faster usually (binary-search + scans)
+
+        let mut block_data_index = 0 as usize;
+        let mut scan_data_index = 0 as usize;
+
+        while scan_data_index < scan_consumer.matching_offsets.len() && block_data_index < self.index_data.len() {
+            let target_offset = scan_consumer.matching_offsets[scan_data_index];
+
+            while block_data_index < self.index_data.len() && self.index_data[block_data_index].0 < target_offset {
+                block_data_index += 1;
+            }
+
+            if block_data_index < self.index_data.len() && self.index_data[block_data_index].0 == target_offset {
+                let arr_start_position = self.index_data[block_data_index].1.to_owned();
+                let arr_end_position = if block_data_index < self.index_data.len()-1 {
+                    self.index_data[block_data_index+1].1.to_owned()
+                } else {
+                    self.str_data.len().to_owned()
+                };
+
+                let val = &self.str_data[arr_start_position..arr_end_position];
+                out_block.append(scan_data_index as u32, val);
+                block_data_index += 1;
+            }
+
+            // Move on regardless
+           scan_data_index += 1;
+        }
+
+        out_block
+    }
+}
+
+// Low cardinality strings - each distinct value is kept once per block and rows refer to it by code
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DictStringBlock {
+    pub dict : Vec<Vec<u8>>,
+    // Pair: offset, index in dict
+    pub codes : TSparseBlock<u32>,
+    // Value -> code, not stored but rebuilt before the dictionary is extended
+    #[serde(skip)]
+    index : HashMap<Vec<u8>, u32>
+}
+
+impl PartialEq for DictStringBlock {
+    fn eq(&self, other : &DictStringBlock) -> bool {
+        self.dict == other.dict && self.codes == other.codes
+    }
+}
+
+impl DictStringBlock {
+    pub fn new() -> DictStringBlock {
+        DictStringBlock { dict: Vec::new(), codes: TSparseBlock { data: Vec::new() }, index: HashMap::new() }
+    }
+
+    pub fn encapsulate_in_block(self) -> Block {
+        Block::DictString(self)
+    }
+
+    pub fn from_string_block(block : &StringBlock) -> DictStringBlock {
+        let mut dict_block = DictStringBlock::new();
+
+        block.for_each_value(|offset, v| {
+            let code = dict_block.code_for(v);
+            dict_block.codes.append(offset, code);
+        });
+
+        dict_block
+    }
+
+    fn code_of(&self, v : &[u8]) -> Option<u32> {
+        // Index is empty when the block was just loaded
+        if self.index.len() == self.dict.len() {
+            return self.index.get(v).cloned();
+        }
+
+        self.dict.iter().position(|entry| entry.as_slice() == v).map(|code| code as u32)
+    }
+
+    // Adds the value to the dictionary when not there yet
+    fn code_for(&mut self, v : &[u8]) -> u32 {
+        if self.index.len() != self.dict.len() {
+            self.index = self.dict.iter().enumerate().map(|(code, entry)| (entry.to_owned(), code as u32)).collect();
+        }
+
+        match self.index.get(v) {
+            Some(code) => *code,
+            None => {
+                let code = self.dict.len() as u32;
+                self.dict.push(v.to_vec());
+                self.index.insert(v.to_vec(), code);
+                code
+            }
+        }
+    }
+
+    pub fn value(&self, code : u32) -> &[u8] {
+        self.dict[code as usize].as_slice()
+    }
+
+    // Rows whose code is set in matching_codes. The predicate is evaluated once per dictionary entry rather than per row.
+    pub fn scan_codes(&self, matching_codes : &Vec<bool>, scan_consumer : &mut BlockScanConsumer) {
+        for &(offset, code) in &self.codes.data {
+            if matching_codes[code as usize] {
+                scan_consumer.matching_offsets.push(offset);
+            }
+        }
+    }
+
+    // Codes of other block are translated to the codes of this one, only the values in use are added to the dictionary
+    fn translate(&mut self, other : &TSparseBlock<u32>, other_dict : &Vec<Vec<u8>>) -> TSparseBlock<u32> {
+        let mut translation:Vec<Option<u32>> = vec![None; other_dict.len()];
+        let mut translated = TSparseBlock { data: Vec::with_capacity(other.data.len()) };
+
+        for &(offset, code) in &other.data {
+            let new_code = match translation[code as usize] {
+                Some(new_code) => new_code,
+                None => {
+                    let new_code = self.code_for(&other_dict[code as usize]);
+                    translation[code as usize] = Some(new_code);
+                    new_code
+                }
+            };
+            translated.data.push((offset, new_code));
         }
 
-        self.index_data = new_index_data;
-        self.str_data = new_str_data;
+        translated
     }
 
-    pub fn transpose_offsets(&mut self, new_offsets : &Vec<u32>) {
-        for i in 0..new_offsets.len() {
-            let record = &mut self.index_data[i];
-            record.0 = new_offsets[i];
+    // Values no longer referred to by any row are removed from the dictionary
+    fn drop_unused_values(&mut self) {
+        let mut used = vec![false; self.dict.len()];
+        for &(_, code) in &self.codes.data {
+            used[code as usize] = true;
+        }
+
+        if used.iter().all(|u| *u) {
+            return;
         }
+
+        let codes = self.codes.to_owned();
+        let dict = mem::replace(&mut self.dict, Vec::new());
+        self.index.clear();
+        self.codes = self.translate(&codes, &dict);
     }
 
-    pub fn move_data(&mut self, target : &mut StringBlock, scan_consumer : &BlockScanConsumer) {
-        let mut temp_block = self.filter_scan_results(scan_consumer);
-        temp_block.transpose_offsets(&scan_consumer.matching_offsets);
+    pub fn append_block(&mut self, other : &DictStringBlock, base_offset : u32) {
+        let translated = self.translate(&other.codes, &other.dict);
+        self.codes.append_block(&translated, base_offset);
+    }
 
-        target.upsert(&temp_block);
-        self.delete(&scan_consumer.matching_offsets);
+    // Only the values of matching rows are copied to the dictionary of the result
+    pub fn filter_scan_results(&self, scan_consumer : &BlockScanConsumer) -> DictStringBlock {
+        let mut out_block = DictStringBlock::new();
+        out_block.codes = out_block.translate(&self.codes.filter_scan_results(scan_consumer), &self.dict);
+        out_block
     }
 
-    pub fn append(&mut self, o: u32, v: &[u8]) {
-        let last_index = self.str_data.len();
-        let str_bytes = v;
-        self.index_data.push((o, last_index));
-        self.str_data.extend_from_slice(str_bytes);
+    pub fn delete(&mut self, offsets : &Vec<u32>) {
+        self.codes.delete(offsets);
+        self.drop_unused_values();
     }
 
-    pub fn filter_scan_results(&self, scan_consumer: &BlockScanConsumer) -> StringBlock {
-        let mut out_block = StringBlock::new();
-        // TODO: binary search-like operations would be faster usually (binary-search + scans)
+    pub fn move_data(&mut self, target : &mut DictStringBlock, scan_consumer : &BlockScanConsumer) {
+        let mut temp_codes = self.codes.filter_scan_results(scan_consumer);
+        temp_codes.transpose_offsets(&scan_consumer.matching_offsets);
 
-        let mut block_data_index = 0 as usize;
-        let mut scan_data_index = 0 as usize;
+        let translated = target.translate(&temp_codes, &self.dict);
+        target.codes.upsert(&translated);
+        target.drop_unused_values();
+        self.delete(&scan_consumer.matching_offsets);
+    }
 
-        while scan_data_index < scan_consumer.matching_offsets.len() && block_data_index < self.index_data.len() {
-            let target_offset = scan_consumer.matching_offsets[scan_data_index];
+    pub fn multi_upsert(&mut self, offsets : &Vec<u32>, v : &[u8]) {
+        let code = self.code_for(v);
+        self.codes.multi_upsert(offsets, code);
+        self.drop_unused_values();
+    }
 
-            while block_data_index < self.index_data.len() && self.index_data[block_data_index].0 < target_offset {
-                block_data_index += 1;
-            }
+    pub fn upsert(&mut self, data : &DictStringBlock) {
+        let translated = self.translate(&data.codes, &data.dict);
+        self.codes.upsert(&translated);
+        self.drop_unused_values();
+    }
+}
 
-            if block_data_index < self.index_data.len() && self.index_data[block_data_index].0 == target_offset {
-                let arr_start_position = self.index_data[block_data_index].1.to_owned();
-                let arr_end_position = if block_data_index < self.index_data.len()-1 {
-                    self.index_data[block_data_index+1].1.to_owned()
-                } else {
-                    self.str_data.len().to_owned()
-                };
+impl Scannable<String> for DictStringBlock {
+    fn scan(&self, op : ScanComparison, str_val : &String, scan_consumer : &mut BlockScanConsumer) {
+        let val = str_val.as_bytes();
 
-                let val = &self.str_data[arr_start_position..arr_end_position];
-                out_block.append(scan_data_index as u32, val);
-                block_data_index += 1;
+        let matching_codes:Vec<bool> = match op {
+            // Just a single code (if any) to look for
+            ScanComparison::Eq | ScanComparison::NotEq => {
+                let code = self.code_of(val);
+                (0..self.dict.len() as u32).map(|c| (Some(c) == code) == (op == ScanComparison::Eq)).collect()
+            },
+            _ => match StringMatcher::new(&op, val) {
+                Ok(matcher) => self.dict.iter().map(|entry| matcher.matches(entry.as_slice())).collect(),
+                // Patterns are validated before scanning, an invalid one just doesn't match
+                Err(_) => return
             }
+        };
 
-            // Move on regardless
-           scan_data_index += 1;
-        }
-
-        out_block
+        self.scan_codes(&matching_codes, scan_consumer);
     }
 }
 
@@ -534,6 +1444,12 @@ impl<T : Clone> TSparseBlock<T> {
         self.data.push((o, v));
     }
 
+    pub fn append_block(&mut self, other : &TSparseBlock<T>, base_offset : u32) {
+        for &(offset, ref value) in &other.data {
+            self.data.push((offset + base_offset, value.to_owned()));
+        }
+    }
+
     pub fn delete(&mut self, offsets: &Vec<u32>) {
         let mut indexes:Vec<usize> = Vec::new();
 
@@ -644,9 +1560,38 @@ impl<T : Clone> TSparseBlock<T> {
 
     }
 
+    pub fn for_each_matching<F>(&self, scan_consumer : &BlockScanConsumer, mut f : F) where F : FnMut(usize, &T) {
+        let mut data_index = 0 as usize;
+
+        for (offsets_index, target_offset) in scan_consumer.matching_offsets.iter().enumerate() {
+            while data_index < self.data.len() && self.data[data_index].0 < *target_offset {
+                data_index += 1;
+            }
+
+            if data_index == self.data.len() {
+                break;
+            }
+
+            if self.data[data_index].0 == *target_offset {
+                f(offsets_index, &self.data[data_index].1);
+                data_index += 1;
+            }
+        }
+    }
+
     pub fn filter_scan_results(&self, scan_consumer : &BlockScanConsumer) -> TSparseBlock<T> {
         let mut out_block = TSparseBlock { data: Vec::new() };
 
+        if !scan_consumer.is_ascending() {
+            // E.g. when reordering the rows, each one needs to be looked up separately then
+            for (offsets_index, target_offset) in scan_consumer.matching_offsets.iter().enumerate() {
+                if let Ok(data_index) = self.data.binary_search_by_key(target_offset, |&(o, _)| o) {
+                    out_block.append(offsets_index as u32, self.data[data_index].1.to_owned());
+                }
+            }
+            return out_block;
+        }
+
         // TODO: binary search-like operations would be faster usually (binary-search + scans)
 
         let mut offsets_index = 0 as usize;
@@ -677,6 +1622,12 @@ pub type Int64SparseBlock = TSparseBlock<u64>;
 pub type Int32SparseBlock = TSparseBlock<u32>;
 pub type Int16SparseBlock = TSparseBlock<u16>;
 pub type Int8SparseBlock = TSparseBlock<u8>;
+pub type Float64SparseBlock = TSparseBlock<f64>;
+pub type Float32SparseBlock = TSparseBlock<f32>;
+pub type SignedInt64SparseBlock = TSparseBlock<i64>;
+pub type SignedInt32SparseBlock = TSparseBlock<i32>;
+pub type SignedInt16SparseBlock = TSparseBlock<i16>;
+pub type SignedInt8SparseBlock = TSparseBlock<i8>;
 
 
 impl Int64SparseBlock {
@@ -715,157 +1666,229 @@ impl Int8SparseBlock {
     }
 }
 
+impl Float64SparseBlock {
+    pub fn new() -> Float64SparseBlock {
+        Float64SparseBlock { data: Vec::new() }
+    }
+    pub fn encapsulate_in_block(self) -> Block {
+        Block::Float64Sparse(self)
+    }
+}
+
+impl Float32SparseBlock {
+    pub fn new() -> Float32SparseBlock {
+        Float32SparseBlock { data: Vec::new() }
+    }
+    pub fn encapsulate_in_block(self) -> Block {
+        Block::Float32Sparse(self)
+    }
+}
+
+impl SignedInt64SparseBlock {
+    pub fn new() -> SignedInt64SparseBlock {
+        SignedInt64SparseBlock { data: Vec::new() }
+    }
+    pub fn encapsulate_in_block(self) -> Block {
+        Block::SignedInt64Sparse(self)
+    }
+}
+
+impl SignedInt32SparseBlock {
+    pub fn new() -> SignedInt32SparseBlock {
+        SignedInt32SparseBlock { data: Vec::new() }
+    }
+    pub fn encapsulate_in_block(self) -> Block {
+        Block::SignedInt32Sparse(self)
+    }
+}
+
+impl SignedInt16SparseBlock {
+    pub fn new() -> SignedInt16SparseBlock {
+        SignedInt16SparseBlock { data: Vec::new() }
+    }
+    pub fn encapsulate_in_block(self) -> Block {
+        Block::SignedInt16Sparse(self)
+    }
+}
+
+impl SignedInt8SparseBlock {
+    pub fn new() -> SignedInt8SparseBlock {
+        SignedInt8SparseBlock { data: Vec::new() }
+    }
+    pub fn encapsulate_in_block(self) -> Block {
+        Block::SignedInt8Sparse(self)
+    }
+}
+
 impl Scannable<u64> for Int64DenseBlock {
     fn scan(&self, op : ScanComparison, val : &u64, scan_consumer : &mut BlockScanConsumer) {
         for (offset_usize, value) in self.data.iter().enumerate() {
-            let offset = offset_usize as u32;
-            match op {
-                ScanComparison::Lt => if value < val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::LtEq => if value <= val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::Eq => if value == val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::GtEq => if value >= val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::Gt => if value > val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::NotEq => if value != val { scan_consumer.matching_offsets.push(offset) },
+            if value_matches(value, &op, val) {
+                scan_consumer.matching_offsets.push(offset_usize as u32);
             }
         }
     }
 }
 
 
-// This is not utf-8 aware
-fn strings_ne_match(s1 : &[u8], op : &ScanComparison, s2 : &[u8]) -> bool {
-    for i in 0..cmp::min(s1.len(), s2.len()) {
-        if s1[i] == s2[i] {
-            // just continue
-        } else {
-            match op {
-                &ScanComparison::LtEq => return s1[i] < s2[i],
-                &ScanComparison::Lt => return s1[i] < s2[i],
-                &ScanComparison::Gt => return s1[i] > s2[i],
-                &ScanComparison::GtEq => return s1[i] > s2[i],
-                //_ => println!("Only <, <=, >=, > matches are handled here..."); return false
-                _ => return false
-            }
+// String filter prepared once per scan, so e.g. the regex is not compiled for every row
+pub enum StringMatcher {
+    Compare(ScanComparison, Vec<u8>),
+    Contains(Vec<u8>),
+    StartsWith(Vec<u8>),
+    EndsWith(Vec<u8>),
+    // Regex and the case insensitive forms
+    Pattern(Regex)
+}
+
+impl StringMatcher {
+    pub fn new(op : &ScanComparison, val : &[u8]) -> Result<StringMatcher, regex::Error> {
+        let val_str = String::from_utf8_lossy(val);
+
+        let pattern = match op {
+            &ScanComparison::Contains => return Ok(StringMatcher::Contains(val.to_vec())),
+            &ScanComparison::StartsWith => return Ok(StringMatcher::StartsWith(val.to_vec())),
+            &ScanComparison::EndsWith => return Ok(StringMatcher::EndsWith(val.to_vec())),
+            &ScanComparison::Regex => val_str.into_owned(),
+            &ScanComparison::ContainsIgnoreCase => format!("(?i){}", regex::escape(&val_str)),
+            &ScanComparison::StartsWithIgnoreCase => format!("(?i)\\A{}", regex::escape(&val_str)),
+            &ScanComparison::EndsWithIgnoreCase => format!("(?i){}\\z", regex::escape(&val_str)),
+            &ScanComparison::RegexIgnoreCase => format!("(?i){}", val_str),
+            _ => return Ok(StringMatcher::Compare(op.to_owned(), val.to_vec()))
+        };
+
+        Regex::new(&pattern).map(StringMatcher::Pattern)
+    }
+
+    pub fn matches(&self, s : &[u8]) -> bool {
+        match self {
+            &StringMatcher::Compare(ref op, ref val) => strings_match(s, op, val),
+            &StringMatcher::Contains(ref val) => val.is_empty() || s.windows(val.len()).any(|window| window == val.as_slice()),
+            &StringMatcher::StartsWith(ref val) => s.starts_with(val),
+            &StringMatcher::EndsWith(ref val) => s.ends_with(val),
+            &StringMatcher::Pattern(ref regex) => regex.is_match(s)
         }
     }
+}
+
+// Strings are compared byte by byte: the first differing byte decides and when one string is a prefix of the other,
+// the shorter one is less. This is not utf-8 aware, though for valid utf-8 it gives the same order as code points.
+fn strings_match(s1 : &[u8], op : &ScanComparison, s2 : &[u8]) -> bool {
+    let ordering = s1.cmp(s2);
 
-    // The shorter string was a substring of the longer one...
     match op {
-        &ScanComparison::LtEq => return s1.len() < s2.len(),
-        &ScanComparison::Lt => return s1.len() < s2.len(),
-        &ScanComparison::Gt => return s1.len() > s2.len(),
-        &ScanComparison::GtEq => return s1.len() > s2.len(),
-        _ => return false
-//        _ => println!("Only <, <=, >=, > matches are handled here...")
+        &ScanComparison::Lt => ordering == Ordering::Less,
+        &ScanComparison::LtEq => ordering != Ordering::Greater,
+        &ScanComparison::Eq => ordering == Ordering::Equal,
+        &ScanComparison::GtEq => ordering != Ordering::Less,
+        &ScanComparison::Gt => ordering == Ordering::Greater,
+        &ScanComparison::NotEq => ordering != Ordering::Equal,
+        _ => false
     }
 }
 
 impl Scannable<String> for StringBlock {
-
-    /// Screams naiive!
     fn scan(&self, op : ScanComparison, str_val : &String, scan_consumer : &mut BlockScanConsumer) {
-        let mut prev_offset = 0 as u32;
-        let mut prev_position = 0 as usize;
-        let val = str_val.as_bytes();
-        let mut index = 0;
-
-        for &(offset_usize, position) in self.index_data.iter() {
-            let size = position - prev_position;
-            let offset = offset_usize as u32;
-
-            if index > 0 {
-                match op {
-                    ScanComparison::Eq => if size == val.len() && val == &self.str_data[prev_position..position] { scan_consumer.matching_offsets.push(prev_offset) },
-//                    ScanComparison::Lt =>
-                    ScanComparison::NotEq => {
-                        if size != val.len() || val != &self.str_data[prev_position..position] { scan_consumer.matching_offsets.push(prev_offset) }
-                    },
-                    _ => if strings_ne_match(&self.str_data[prev_position..position], &op, val) { scan_consumer.matching_offsets.push(prev_offset) }
-                }
-            }
-
-            prev_position = position;
-            prev_offset = offset;
-            index += 1;
-        }
+        // Patterns are validated before scanning, an invalid one just doesn't match
+        let matcher = match StringMatcher::new(&op, str_val.as_bytes()) {
+            Ok(matcher) => matcher,
+            Err(_) => return
+        };
+
+        self.for_each_value(|offset, value| if matcher.matches(value) {
+            scan_consumer.matching_offsets.push(offset);
+        });
+    }
+}
 
-        // last element
-        // TODO: extract/refactor
+fn value_matches<T : PartialOrd>(value : &T, op : &ScanComparison, val : &T) -> bool {
+    match op {
+        &ScanComparison::Lt => value < val,
+        &ScanComparison::LtEq => value <= val,
+        &ScanComparison::Eq => value == val,
+        &ScanComparison::GtEq => value >= val,
+        &ScanComparison::Gt => value > val,
+        &ScanComparison::NotEq => value != val,
+        // String only comparisons, not accepted for other columns
+        _ => false
+    }
+}
 
-        if index > 0 {
-            let size = self.str_data.len() - prev_position;
-            let offset = prev_offset;
-            let position = self.str_data.len();
+// Range bounds are the first two values
+fn values_match<T : PartialOrd>(value : &T, op : &ScanComparison, vals : &Vec<T>) -> bool {
+    match op {
+        &ScanComparison::In => vals.iter().any(|v| v == value),
+        &ScanComparison::Between => vals.len() == 2 && vals[0] <= *value && *value <= vals[1],
+        &ScanComparison::BetweenHalfOpen => vals.len() == 2 && vals[0] <= *value && *value < vals[1],
+        _ => false
+    }
+}
 
-            match op {
-                ScanComparison::Eq => if size == val.len() && val == &self.str_data[prev_position..position] { scan_consumer.matching_offsets.push(prev_offset) },
-                ScanComparison::NotEq => if size != val.len() || val != &self.str_data[prev_position..position] { scan_consumer.matching_offsets.push(prev_offset) },
-                _ => if strings_ne_match(&self.str_data[prev_position..position], &op, val) { scan_consumer.matching_offsets.push(prev_offset) }
+impl<T : Clone + PartialOrd> MultiScannable<T> for TSparseBlock<T> {
+    fn scan_multi(&self, op : ScanComparison, vals : &Vec<T>, scan_consumer : &mut BlockScanConsumer) {
+        for &(offset, ref value) in self.data.iter() {
+            if values_match(value, &op, vals) {
+                scan_consumer.matching_offsets.push(offset);
             }
         }
-
     }
 }
 
-impl Scannable<u64> for Int64SparseBlock {
-    fn scan(&self, op : ScanComparison, val : &u64, scan_consumer : &mut BlockScanConsumer) {
-        for &(offset, value_ref) in self.data.iter() {
-            let value = &value_ref;
-            match op {
-                ScanComparison::Lt => if value < val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::LtEq => if value <= val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::Eq => if value == val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::GtEq =>  if value >= val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::Gt => if value > val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::NotEq => if value != val { scan_consumer.matching_offsets.push(offset) },
+impl MultiScannable<u64> for Int64DenseBlock {
+    fn scan_multi(&self, op : ScanComparison, vals : &Vec<u64>, scan_consumer : &mut BlockScanConsumer) {
+        for (offset, value) in self.data.iter().enumerate() {
+            if values_match(value, &op, vals) {
+                scan_consumer.matching_offsets.push(offset as u32);
             }
         }
     }
 }
 
-impl Scannable<u32> for Int32SparseBlock {
-    fn scan(&self, op : ScanComparison, val : &u32, scan_consumer : &mut BlockScanConsumer) {
-        for &(offset, value_ref) in self.data.iter() {
-            let value = &value_ref;
-            match op {
-                ScanComparison::Lt => if value < val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::LtEq => if value <= val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::Eq => if value == val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::GtEq => if value >= val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::Gt => if value > val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::NotEq => if value != val { scan_consumer.matching_offsets.push(offset) },
+impl MultiScannable<f64> for Float64DenseBlock {
+    fn scan_multi(&self, op : ScanComparison, vals : &Vec<f64>, scan_consumer : &mut BlockScanConsumer) {
+        for (offset, value) in self.data.iter().enumerate() {
+            if values_match(value, &op, vals) {
+                scan_consumer.matching_offsets.push(offset as u32);
             }
         }
     }
 }
 
-impl Scannable<u16> for Int16SparseBlock {
-    fn scan(&self, op : ScanComparison, val : &u16, scan_consumer : &mut BlockScanConsumer) {
-        for &(offset, value_ref) in self.data.iter() {
-            let value = &value_ref;
-            match op {
-                ScanComparison::Lt => if value < val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::LtEq => if value <= val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::Eq => if value == val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::GtEq => if value >= val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::Gt => if value > val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::NotEq => if value != val { scan_consumer.matching_offsets.push(offset) },
+// Strings are compared byte by byte
+impl MultiScannable<Vec<u8>> for StringBlock {
+    fn scan_multi(&self, op : ScanComparison, vals : &Vec<Vec<u8>>, scan_consumer : &mut BlockScanConsumer) {
+        let vals:Vec<&[u8]> = vals.iter().map(|v| v.as_slice()).collect();
+
+        self.for_each_value(|offset, value| if values_match(&value, &op, &vals) {
+            scan_consumer.matching_offsets.push(offset);
+        });
+    }
+}
+
+impl MultiScannable<Vec<u8>> for DictStringBlock {
+    fn scan_multi(&self, op : ScanComparison, vals : &Vec<Vec<u8>>, scan_consumer : &mut BlockScanConsumer) {
+        let vals:Vec<&[u8]> = vals.iter().map(|v| v.as_slice()).collect();
+        let matching_codes:Vec<bool> = self.dict.iter().map(|entry| values_match(&entry.as_slice(), &op, &vals)).collect();
+        self.scan_codes(&matching_codes, scan_consumer);
+    }
+}
+
+// Mind that for floats NaN matches only NotEq
+impl<T : Clone + PartialOrd> Scannable<T> for TSparseBlock<T> {
+    fn scan(&self, op : ScanComparison, val : &T, scan_consumer : &mut BlockScanConsumer) {
+        for &(offset, ref value) in self.data.iter() {
+            if value_matches(value, &op, val) {
+                scan_consumer.matching_offsets.push(offset);
             }
         }
     }
 }
 
-impl Scannable<u8> for Int8SparseBlock {
-    fn scan(&self, op : ScanComparison, val : &u8, scan_consumer : &mut BlockScanConsumer) {
-        for &(offset, value_ref) in self.data.iter() {
-            let value = &value_ref;
-            match op {
-                ScanComparison::Lt => if value < val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::LtEq => if value <= val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::Eq => if value == val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::GtEq => if value >= val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::Gt => if value > val { scan_consumer.matching_offsets.push(offset) },
-                ScanComparison::NotEq => if value != val { scan_consumer.matching_offsets.push(offset) },
+impl Scannable<f64> for Float64DenseBlock {
+    fn scan(&self, op : ScanComparison, val : &f64, scan_consumer : &mut BlockScanConsumer) {
+        for (offset, value) in self.data.iter().enumerate() {
+            if value_matches(value, &op, val) {
+                scan_consumer.matching_offsets.push(offset as u32);
             }
         }
     }
@@ -1090,3 +2113,343 @@ fn it_filters_sparse_block() {
 
     assert_eq!(expected_output, actual_output);
 }
+
+#[test]
+fn append_string_block() {
+    let mut block = StringBlock::new();
+    block.append(0, "foo".as_bytes());
+    block.append(2, "bar".as_bytes());
+
+    let mut other = StringBlock::new();
+    other.append(1, "snafu".as_bytes());
+
+    let mut expected_block = StringBlock::new();
+    expected_block.append(0, "foo".as_bytes());
+    expected_block.append(2, "bar".as_bytes());
+    expected_block.append(4, "snafu".as_bytes());
+
+    block.append_block(&other, 3);
+
+    assert_eq!(expected_block, block);
+}
+
+#[test]
+fn it_visits_matching_values() {
+    let data_block = Block::Int32Sparse(Int32SparseBlock {
+        data: vec![
+            (1, 100),
+            (2, 200),
+            (6, 600)
+        ]
+    });
+
+    let mut str_block = StringBlock::new();
+    str_block.append(0, "foo".as_bytes());
+    str_block.append(2, "bar".as_bytes());
+    str_block.append(3, "snafu".as_bytes());
+
+    let scan_consumer = BlockScanConsumer {
+        matching_offsets: vec![0,2,6,7]
+    };
+
+    let mut visited = Vec::new();
+    data_block.for_each_matching(&scan_consumer, |position, v| if let ScalarValue::UInt(x) = v { visited.push((position, x)) });
+    assert_eq!(vec![(1, 200), (2, 600)], visited);
+
+    let mut visited_str = Vec::new();
+    Block::StringBlock(str_block).for_each_matching(&scan_consumer, |position, v| if let ScalarValue::Str(x) = v { visited_str.push((position, x.to_vec())) });
+    assert_eq!(vec![(0, "foo".as_bytes().to_vec()), (1, "bar".as_bytes().to_vec())], visited_str);
+}
+
+#[test]
+fn it_filters_sparse_block_in_any_order() {
+    let data_block = Int64SparseBlock {
+        data: vec![
+            (1, 100),
+            (2, 200),
+            (6, 600)
+        ]
+    };
+
+    let mut str_block = StringBlock::new();
+    str_block.append(0, "foo".as_bytes());
+    str_block.append(2, "bar".as_bytes());
+    str_block.append(6, "snafu".as_bytes());
+
+    let scan_consumer = BlockScanConsumer {
+        matching_offsets: vec![6,0,2]
+    };
+
+    assert_eq!(Int64SparseBlock { data: vec![(0, 600), (2, 200)] }, data_block.filter_scan_results(&scan_consumer));
+
+    let mut expected_block = StringBlock::new();
+    expected_block.append(0, "snafu".as_bytes());
+    expected_block.append(1, "foo".as_bytes());
+    expected_block.append(2, "bar".as_bytes());
+    assert_eq!(expected_block, str_block.filter_scan_results(&scan_consumer));
+}
+
+#[test]
+fn it_widens_blocks() {
+    let block = Block::Int16Sparse(Int16SparseBlock { data: vec![(0, 65535), (1, 7)] });
+
+    assert_eq!(Some(Block::Int32Sparse(Int32SparseBlock { data: vec![(0, 65535), (1, 7)] })), block.widen(&BlockType::Int32Sparse, 2));
+    assert_eq!(Some(Block::Int64Dense(Int64DenseBlock { data: vec![65535, 7] })), block.widen(&BlockType::Int64Dense, 2));
+
+    // Not set in all rows, narrowing or not an integer at all
+    assert_eq!(None, block.widen(&BlockType::Int64Dense, 3));
+    assert_eq!(None, block.widen(&BlockType::Int8Sparse, 2));
+    assert_eq!(None, block.widen(&BlockType::String, 2));
+}
+
+#[test]
+fn it_scans_and_upserts_float_blocks() {
+    let dense = Block::Float64Dense(Float64DenseBlock { data: vec![1.5, -2.0, 3.25] });
+    let mut sparse = Block::Float32Sparse(Float32SparseBlock { data: vec![(0, 0.5), (2, -1.5), (5, 2.0)] });
+
+    let mut consumer = BlockScanConsumer::new();
+    dense.scan(ScanComparison::Gt, &1.0, &mut consumer);
+    assert_eq!(vec![0, 2], consumer.matching_offsets);
+    assert_eq!(Block::Float64Dense(Float64DenseBlock { data: vec![1.5, 3.25] }), dense.consume(&consumer));
+
+    let mut consumer = BlockScanConsumer::new();
+    sparse.scan(ScanComparison::LtEq, &0.5, &mut consumer);
+    assert_eq!(vec![0, 2], consumer.matching_offsets);
+
+    sparse.multi_upsert(&vec![2, 3], &7.5f32);
+    assert_eq!(Block::Float32Sparse(Float32SparseBlock { data: vec![(0, 0.5), (2, 7.5), (3, 7.5), (5, 2.0)] }), sparse);
+
+    sparse.delete(&vec![0, 5]);
+    assert_eq!(Block::Float32Sparse(Float32SparseBlock { data: vec![(2, 7.5), (3, 7.5)] }), sparse);
+}
+
+#[test]
+fn it_scans_signed_blocks() {
+    let mut block = Block::SignedInt32Sparse(SignedInt32SparseBlock { data: vec![(0, -5), (1, 3), (3, -1), (4, 0)] });
+
+    let mut consumer = BlockScanConsumer::new();
+    block.scan(ScanComparison::Lt, &0i64, &mut consumer);
+    assert_eq!(vec![0, 3], consumer.matching_offsets);
+    assert_eq!(Block::SignedInt32Sparse(SignedInt32SparseBlock { data: vec![(0, -5), (1, -1)] }), block.consume(&consumer));
+
+    // As passed in ScanFilter.val
+    let mut consumer = BlockScanConsumer::new();
+    block.scan(ScanComparison::GtEq, &((-1i64) as u64 as i64), &mut consumer);
+    assert_eq!(vec![1, 3, 4], consumer.matching_offsets);
+
+    block.multi_upsert(&vec![1, 2], &-7i32);
+    assert_eq!(Block::SignedInt32Sparse(SignedInt32SparseBlock { data: vec![(0, -5), (1, -7), (2, -7), (3, -1), (4, 0)] }), block);
+}
+
+#[test]
+fn it_scans_narrow_blocks_with_values_out_of_range() {
+    let block = Block::SignedInt8Sparse(SignedInt8SparseBlock { data: vec![(0, -100), (1, 5), (3, 100)] });
+
+    let matching = |op : ScanComparison, val : i64| {
+        let mut consumer = BlockScanConsumer::new();
+        block.scan(op, &val, &mut consumer);
+        consumer.matching_offsets
+    };
+
+    assert_eq!(vec![0, 1, 3], matching(ScanComparison::Gt, -200));
+    assert_eq!(Vec::<u32>::new(), matching(ScanComparison::Lt, -200));
+    assert_eq!(vec![0, 1, 3], matching(ScanComparison::LtEq, 200));
+    assert_eq!(Vec::<u32>::new(), matching(ScanComparison::GtEq, 200));
+    assert_eq!(Vec::<u32>::new(), matching(ScanComparison::Eq, 5 + 256));
+    assert_eq!(vec![0, 1, 3], matching(ScanComparison::NotEq, 5 + 256));
+
+    let block = Block::Int8Sparse(Int8SparseBlock { data: vec![(0, 5), (2, 200)] });
+    let mut consumer = BlockScanConsumer::new();
+    block.scan(ScanComparison::Eq, &(5u64 + 256), &mut consumer);
+    assert_eq!(Vec::<u32>::new(), consumer.matching_offsets);
+
+    let mut consumer = BlockScanConsumer::new();
+    block.scan(ScanComparison::Lt, &1000u64, &mut consumer);
+    assert_eq!(vec![0, 2], consumer.matching_offsets);
+}
+
+#[test]
+fn it_scans_and_modifies_bitmap_blocks() {
+    let mut block = BitmapBlock::new();
+    block.data.extend(vec![1, 3, 4]);
+
+    let mut consumer = BlockScanConsumer::new();
+    block.scan(ScanComparison::Eq, false, 6, &mut consumer);
+    assert_eq!(vec![0, 2, 5], consumer.matching_offsets);
+
+    let mut consumer = BlockScanConsumer::new();
+    block.scan(ScanComparison::NotEq, false, 6, &mut consumer);
+    assert_eq!(vec![1, 3, 4], consumer.matching_offsets);
+
+    // Materialized rows 0, 1, 2
+    consumer.matching_offsets = vec![2, 3, 4];
+    let mut expected = BitmapBlock::new();
+    expected.data.extend(vec![1, 2]);
+    assert_eq!(expected, block.filter_scan_results(&consumer));
+
+    let mut target = BitmapBlock::new();
+    block.move_data(&mut target, &consumer);
+    assert_eq!(vec![1], block.data.iter().collect::<Vec<u32>>());
+    assert_eq!(vec![3, 4], target.data.iter().collect::<Vec<u32>>());
+
+    target.multi_upsert(&vec![0, 3], false);
+    target.delete(&vec![4]);
+    assert!(target.data.is_empty());
+}
+
+#[test]
+fn it_encodes_strings_with_dictionary() {
+    let string_block = StringBlock { index_data: vec![(0, 0), (2, 4), (3, 8), (5, 12)], str_data: "infowarninfoinfo".as_bytes().to_vec() };
+    let mut block = DictStringBlock::from_string_block(&string_block);
+    assert_eq!(vec!["info".as_bytes().to_vec(), "warn".as_bytes().to_vec()], block.dict);
+    assert_eq!(vec![(0, 0), (2, 1), (3, 0), (5, 0)], block.codes.data);
+
+    let mut consumer = BlockScanConsumer::new();
+    block.scan(ScanComparison::Eq, &String::from("info"), &mut consumer);
+    assert_eq!(vec![0, 3, 5], consumer.matching_offsets);
+
+    let mut not_found = BlockScanConsumer::new();
+    block.scan(ScanComparison::NotEq, &String::from("error"), &mut not_found);
+    assert_eq!(vec![0, 2, 3, 5], not_found.matching_offsets);
+
+    // Appended codes are translated to the existing dictionary
+    let mut other = DictStringBlock::new();
+    other.multi_upsert(&vec![0, 1], "error".as_bytes());
+    other.multi_upsert(&vec![1], "warn".as_bytes());
+    block.append_block(&other, 6);
+    assert_eq!(3, block.dict.len());
+    assert_eq!(vec![(0, 0), (2, 1), (3, 0), (5, 0), (6, 2), (7, 1)], block.codes.data);
+
+    let mut target = DictStringBlock::new();
+    block.move_data(&mut target, &consumer);
+    assert_eq!(vec!["info".as_bytes().to_vec()], target.dict);
+    assert_eq!(vec![(0, 0), (3, 0), (5, 0)], target.codes.data);
+    // Moved out value is no longer in the dictionary
+    assert_eq!(vec!["warn".as_bytes().to_vec(), "error".as_bytes().to_vec()], block.dict);
+    assert_eq!(vec![(2, 0), (6, 1), (7, 0)], block.codes.data);
+
+    // Results carry only the values of the matching rows
+    consumer.matching_offsets = vec![6];
+    let filtered = block.filter_scan_results(&consumer);
+    assert_eq!(vec!["error".as_bytes().to_vec()], filtered.dict);
+    assert_eq!(vec![(0, 0)], filtered.codes.data);
+
+    block.multi_upsert(&vec![2, 7], "error".as_bytes());
+    assert_eq!(vec!["error".as_bytes().to_vec()], block.dict);
+    assert_eq!(vec![(2, 0), (6, 0), (7, 0)], block.codes.data);
+
+    // Lookups of a loaded block, which has no index yet
+    let loaded:DictStringBlock = deserialize(&serialize(&block, Infinite).unwrap()).unwrap();
+    assert_eq!(block, loaded);
+    let mut consumer = BlockScanConsumer::new();
+    loaded.scan(ScanComparison::Eq, &String::from("error"), &mut consumer);
+    assert_eq!(vec![2, 6, 7], consumer.matching_offsets);
+}
+
+#[test]
+fn it_scans_string_patterns() {
+    let mut block = StringBlock::new();
+    for (offset, s) in vec!["/api/users", "/static/app.js", "/API/Events", "/health"].into_iter().enumerate() {
+        block.append(offset as u32, s.as_bytes());
+    }
+
+    let matching = |op : ScanComparison, val : &str| {
+        let mut consumer = BlockScanConsumer::new();
+        block.scan(op, &String::from(val), &mut consumer);
+        consumer.matching_offsets
+    };
+
+    assert_eq!(vec![0], matching(ScanComparison::StartsWith, "/api"));
+    assert_eq!(vec![0, 2], matching(ScanComparison::StartsWithIgnoreCase, "/api"));
+    assert_eq!(vec![1], matching(ScanComparison::EndsWith, ".js"));
+    assert_eq!(vec![0, 1, 2], matching(ScanComparison::EndsWithIgnoreCase, "S"));
+    assert_eq!(vec![0, 1, 3], matching(ScanComparison::Contains, "a"));
+    assert_eq!(vec![0, 1, 2, 3], matching(ScanComparison::Contains, ""));
+    assert_eq!(vec![0, 1, 2, 3], matching(ScanComparison::ContainsIgnoreCase, "A"));
+    assert_eq!(vec![0, 2], matching(ScanComparison::Regex, "^/(api|API)/[A-Za-z]+$"));
+    assert_eq!(vec![2], matching(ScanComparison::RegexIgnoreCase, "events$"));
+    // Regex special characters are taken literally
+    assert_eq!(vec![1], matching(ScanComparison::ContainsIgnoreCase, "APP.JS"));
+    assert_eq!(Vec::<u32>::new(), matching(ScanComparison::ContainsIgnoreCase, "app.j*"));
+    // Invalid pattern
+    assert_eq!(Vec::<u32>::new(), matching(ScanComparison::Regex, "(api"));
+}
+
+#[test]
+fn it_scans_value_lists_and_ranges() {
+    let matching = |block : &Block, op : ScanComparison, vals : Vec<u64>| {
+        let mut consumer = BlockScanConsumer::new();
+        block.scan_multi(op, &vals, &mut consumer);
+        consumer.matching_offsets
+    };
+
+    let dense = Block::Int64Dense(Int64DenseBlock { data: vec![1, 4, 9, 4, 7] });
+    assert_eq!(vec![0, 1, 2, 3], matching(&dense, ScanComparison::In, vec![9, 4, 1]));
+    assert_eq!(vec![1, 3, 4], matching(&dense, ScanComparison::Between, vec![4, 7]));
+    assert_eq!(vec![1, 3], matching(&dense, ScanComparison::BetweenHalfOpen, vec![4, 7]));
+    assert_eq!(Vec::<u32>::new(), matching(&dense, ScanComparison::In, vec![]));
+
+    // Values beyond the u8 range are not truncated
+    let sparse = Block::Int8Sparse(Int8SparseBlock { data: vec![(1, 0), (3, 255), (4, 10)] });
+    assert_eq!(vec![1], matching(&sparse, ScanComparison::In, vec![256, 0]));
+    assert_eq!(vec![3, 4], matching(&sparse, ScanComparison::BetweenHalfOpen, vec![5, 1000]));
+    assert_eq!(Vec::<u32>::new(), matching(&sparse, ScanComparison::Between, vec![256, 1000]));
+
+    let signed = Block::SignedInt16Sparse(SignedInt16SparseBlock { data: vec![(0, -300), (2, -5), (3, 12)] });
+    let mut consumer = BlockScanConsumer::new();
+    signed.scan_multi(ScanComparison::Between, &vec![-100000i64, 0], &mut consumer);
+    assert_eq!(vec![0, 2], consumer.matching_offsets);
+
+    let mut strings = StringBlock::new();
+    for (offset, s) in vec!["debug", "error", "info", "warn"].into_iter().enumerate() {
+        strings.append(offset as u32, s.as_bytes());
+    }
+    let mut consumer = BlockScanConsumer::new();
+    strings.scan_multi(ScanComparison::In, &vec!["warn".as_bytes().to_vec(), "error".as_bytes().to_vec()], &mut consumer);
+    assert_eq!(vec![1, 3], consumer.matching_offsets);
+
+    let dict = DictStringBlock::from_string_block(&strings);
+    let mut consumer = BlockScanConsumer::new();
+    dict.scan_multi(ScanComparison::BetweenHalfOpen, &vec!["e".as_bytes().to_vec(), "info".as_bytes().to_vec()], &mut consumer);
+    assert_eq!(vec![1], consumer.matching_offsets);
+}
+
+#[test]
+fn it_compares_strings_lexicographically() {
+    let values = vec!["", "a", "ab", "abc", "abd", "b", "ba", "\u{e9}", "\u{e9}t\u{e9}"];
+    let ops = vec![ScanComparison::Lt, ScanComparison::LtEq, ScanComparison::Eq, ScanComparison::GtEq, ScanComparison::Gt, ScanComparison::NotEq];
+
+    let mut block = StringBlock::new();
+    for (offset, s) in values.iter().enumerate() {
+        block.append(offset as u32, s.as_bytes());
+    }
+
+    // Every value against every other, including the last element of the block
+    for val in &values {
+        for op in &ops {
+            let mut consumer = BlockScanConsumer::new();
+            block.scan(op.to_owned(), &String::from(*val), &mut consumer);
+
+            let expected:Vec<u32> = values.iter().enumerate()
+                .filter(|&(_, s)| match op {
+                    &ScanComparison::Lt => s < val,
+                    &ScanComparison::LtEq => s <= val,
+                    &ScanComparison::Eq => s == val,
+                    &ScanComparison::GtEq => s >= val,
+                    &ScanComparison::Gt => s > val,
+                    _ => s != val
+                })
+                .map(|(offset, _)| offset as u32)
+                .collect();
+
+            assert_eq!(expected, consumer.matching_offsets, "{:?} {:?}", op, val);
+        }
+    }
+
+    assert!(strings_match(b"abc", &ScanComparison::LtEq, b"abc"));
+    assert!(strings_match(b"abc", &ScanComparison::GtEq, b"abc"));
+    assert!(strings_match(b"ab", &ScanComparison::Lt, b"abc"));
+    assert!(strings_match(b"abd", &ScanComparison::Gt, b"abc"));
+    assert!(!strings_match(b"b", &ScanComparison::LtEq, b"abc"));
+    assert!(!strings_match(b"abc", &ScanComparison::Lt, b"abc"));
+}
@@ -3,9 +3,12 @@ extern crate serde_derive;
 extern crate serde;
 extern crate bincode;
 extern crate nanomsg;
+extern crate regex;
+extern crate roaring;
 
 extern crate rand;
 use rand::Rng;
+use std::process;
 use std::time::Instant;
 
 
@@ -14,6 +17,9 @@ pub mod scan;
 pub mod partition;
 pub mod int_blocks;
 pub mod api;
+pub mod aggregate;
+pub mod cursor;
+pub mod error;
 pub mod manager;
 pub mod nanomsg_endpoint;
 
@@ -134,11 +140,11 @@ fn prepare_fake_data(manager : &mut Manager) {
     for iter in 0..100 {
         let msg = create_message(cur_ts + iter*17);
         total_count += msg.row_count as usize;
-        manager.insert(&msg);
+        manager.insert(&msg).unwrap();
     }
 
-    manager.store_catalog();
-    manager.dump_in_mem_partition();
+    manager.store_catalog().unwrap();
+    manager.dump_in_mem_partition().unwrap();
 
     println!("Creating {} records took {:?}", total_count, create_duration.elapsed());
 }
@@ -151,13 +157,13 @@ fn prepare_demo_scan(manager : &mut Manager) {
     let mut total_materialized = 0;
 
     for part_info in &manager.catalog.available_partitions {
-        let scanned_block = manager.load_block(&part_info, 7);
+        let scanned_block = manager.load_block(&part_info, 7).unwrap();
         let mut consumer = BlockScanConsumer{matching_offsets : Vec::new()};
         scanned_block.scan(ScanComparison::LtEq, &(1363258435234989944 as u64), &mut consumer);
 
         let mut scan_msg = ScanResultMessage::new();
         let mut cache = BlockCache::new(part_info);
-        consumer.materialize(&manager, &mut cache, &vec![0,1,3,4,5,24], &mut scan_msg);
+        consumer.materialize(&manager, &mut cache, &vec![0,1,3,4,5,24], &mut scan_msg).unwrap();
 
         total_materialized += scan_msg.row_count;
         total_matched += consumer.matching_offsets.len();
@@ -172,7 +178,10 @@ fn main() {
 //    prepare_catalog(&mut manager);
 //    prepare_fake_data(&mut manager);
 
-    manager.reload_catalog();
+    if let Err(e) = manager.reload_catalog() {
+        println!("Loading catalog failed: {}", e);
+        process::exit(1);
+    }
 
     for part in &manager.catalog.available_partitions {
         println!("Partition: {} for range [{} - {}]", part.id, part.min_ts, part.max_ts);
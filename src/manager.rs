@@ -3,21 +3,27 @@ use catalog::BlockType;
 use catalog::PartitionInfo;
 use partition::{Partition, PartitionMetadata};
 use int_blocks::{Block, Int32SparseBlock, Int64DenseBlock, Int64SparseBlock, StringBlock};
-use api::{InsertMessage, DataCompactionRequest, ScanFilter, ScanComparison, PartialInsertMessage, handle_data_compaction};
+use api::{InsertMessage, NamedInsertMessage, NamedInsertResponse, DataCompactionRequest, ScanFilter, ScanComparison, PartialInsertMessage, handle_data_compaction};
+use error;
 
-use bincode::{serialize, deserialize, Infinite};
+use bincode::{serialize, deserialize, deserialize_from, Infinite};
 use serde::ser::{Serialize};
+use serde::de::DeserializeOwned;
 use std::fs;
-use std::error::Error;
+use std::io;
 use std::io::prelude::*;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
+use std::io::Cursor;
 use std::path::Path;
 use std::io::BufReader;
+use std::cmp;
 
 pub struct Manager {
     pub db_home: String,
     pub catalog: Catalog,
-    pub current_partition: Partition
+    pub current_partition: Partition,
+    // Set when some of the dropped columns might still have their blocks stored
+    pub purge_pending: bool
 }
 
 // To be used only within extremely limited context
@@ -49,190 +55,545 @@ impl BlockCache {
         Option::None
     }
 
-//    fn fetch_block(&'a self, block_index : u32) -> Block {
-//        manager.load_block(&self.partition_info, block_index)
-//    }
+    pub fn get_cached_or_load<'a>(&'a mut self, manager : &Manager, block_index : u32) -> error::Result<&'a Block> {
+        if self.cached_block_maybe(block_index).is_none() {
+            let block = manager.load_block(&self.partition_info, block_index)?;
+            self.cache_block(block, block_index);
+        }
 
-//    pub fn get_cached_or_load(&mut self, manager : &Manager, block_index : u32) -> &Block {
-//        let block_option = self.find_cached_block(block_index);
-//
-//        match block_option {
-//            None => {
-//                let block = manager.load_block(&self.partition_info, block_index);
-//                self.cache.push((block_index, &block));
-//                return &block;
-//            }
-//            Some(ref x) => {
-//                return x;
-//            }
-//        }
-//    }
+        Ok(self.cached_block_maybe(block_index).unwrap())
+    }
+}
+
+// The id is assigned upfront (rather than in Partition::prepare) so scans can tell the in memory partition apart
+fn create_in_mem_partition() -> Partition {
+    let mut part = Partition::new();
+    part.metadata.id = Partition::create_partition_id(&part.metadata);
+    part
 }
 
 fn ensure_partition_is_current(catalog: &Catalog, part: &mut Partition) {
     if part.blocks.len() < catalog.columns.len() {
+        let row_count = part.blocks.first().map_or(0, |block| block.len());
+
         for block_no in part.blocks.len()..catalog.columns.len() {
-            part.blocks.push(Block::create_block(&catalog.columns[block_no].data_type));
+            part.blocks.push(Block::create_block_for_rows(&catalog.columns[block_no].data_type, row_count));
         }
     }
 }
 
-fn save_data<T: Serialize>(path : &String, data : &T) {
-    let mut file = File::create(path).expect("Unable to create file");
+fn offsets_are_valid<I: Iterator<Item=u32>>(offsets : I, row_count : u32) -> bool {
+    let mut prev:Option<u32> = None;
+
+    for offset in offsets {
+        if offset >= row_count || prev.map_or(false, |prev| offset <= prev) {
+            return false;
+        }
+        prev = Some(offset);
+    }
 
-    let bytes:Vec<u8> = serialize(data, Infinite).unwrap();
-    file.write_all(&bytes).unwrap();
+    true
 }
 
-fn read_block(path : &String) -> Block {
-    println!("Reading block {}", path);
+pub fn validate_insert(catalog : &Catalog, msg : &InsertMessage) -> error::Result<()> {
+    if msg.col_types.len() != msg.col_count as usize || msg.blocks.len() != msg.col_count as usize {
+        return Err(error::Error::BadPayload(format!("Expected {} columns, got {} types and {} blocks", msg.col_count, msg.col_types.len(), msg.blocks.len())));
+    }
+
+    let mut seen_columns:Vec<u32> = Vec::new();
+
+    for (&(col_index, ref col_type), block) in msg.col_types.iter().zip(msg.blocks.iter()) {
+        let column = match catalog.column(col_index) {
+            Some(column) => column,
+            None => return Err(error::Error::UnknownColumn(col_index))
+        };
+
+        if seen_columns.contains(&col_index) {
+            return Err(error::Error::BadPayload(format!("Column {} is present more than once", col_index)));
+        }
+        seen_columns.push(col_index);
+
+        if *col_type != column.data_type || block.data_type() != column.data_type {
+            return Err(error::Error::TypeMismatch(format!("Column {} is {:?}, got {:?} declared as {:?}", col_index, column.data_type, block.data_type(), col_type)));
+        }
 
-    let file = File::open(path).unwrap();
+        let offsets_valid = match block {
+            &Block::Int64Dense(ref b) => {
+                if b.data.len() != msg.row_count as usize {
+                    return Err(error::Error::BadPayload(format!("Dense column {} has {} values for {} rows", col_index, b.data.len(), msg.row_count)));
+                }
+                true
+            },
+            &Block::Int64Sparse(ref b) => offsets_are_valid(b.data.iter().map(|pair| pair.0), msg.row_count),
+            &Block::Int32Sparse(ref b) => offsets_are_valid(b.data.iter().map(|pair| pair.0), msg.row_count),
+            &Block::Int16Sparse(ref b) => offsets_are_valid(b.data.iter().map(|pair| pair.0), msg.row_count),
+            &Block::Int8Sparse(ref b) => offsets_are_valid(b.data.iter().map(|pair| pair.0), msg.row_count),
+            &Block::Float64Dense(ref b) => {
+                if b.data.len() != msg.row_count as usize {
+                    return Err(error::Error::BadPayload(format!("Dense column {} has {} values for {} rows", col_index, b.data.len(), msg.row_count)));
+                }
+                true
+            },
+            &Block::Float64Sparse(ref b) => offsets_are_valid(b.data.iter().map(|pair| pair.0), msg.row_count),
+            &Block::Float32Sparse(ref b) => offsets_are_valid(b.data.iter().map(|pair| pair.0), msg.row_count),
+            &Block::SignedInt64Sparse(ref b) => offsets_are_valid(b.data.iter().map(|pair| pair.0), msg.row_count),
+            &Block::SignedInt32Sparse(ref b) => offsets_are_valid(b.data.iter().map(|pair| pair.0), msg.row_count),
+            &Block::SignedInt16Sparse(ref b) => offsets_are_valid(b.data.iter().map(|pair| pair.0), msg.row_count),
+            &Block::SignedInt8Sparse(ref b) => offsets_are_valid(b.data.iter().map(|pair| pair.0), msg.row_count),
+            &Block::Bitmap(ref b) => offsets_are_valid(b.data.iter(), msg.row_count),
+            &Block::DictString(ref b) => {
+                b.codes.data.iter().all(|pair| (pair.1 as usize) < b.dict.len())
+                    && offsets_are_valid(b.codes.data.iter().map(|pair| pair.0), msg.row_count)
+            },
+            &Block::StringBlock(ref b) => {
+                // Each string spans from its position to the position of the next one (or the end of data)
+                let positions_valid = b.index_data.windows(2).all(|w| w[0].1 <= w[1].1)
+                    && b.index_data.last().map_or(true, |last| last.1 <= b.str_data.len());
+
+                positions_valid && offsets_are_valid(b.index_data.iter().map(|pair| pair.0), msg.row_count)
+            }
+        };
+
+        if !offsets_valid {
+            return Err(error::Error::BadPayload(format!("Offsets in column {} are out of range or not strictly increasing", col_index)));
+        }
+    }
+
+    if !seen_columns.contains(&0) {
+        return Err(error::Error::BadPayload(String::from("The ts column is missing")));
+    }
+
+    // Otherwise values of the next inserts would be appended to the rows of this one
+    for (col_index, column) in catalog.live_columns() {
+        if column.data_type.is_dense() && !seen_columns.contains(&col_index) {
+            return Err(error::Error::BadPayload(format!("Dense column {} is missing", col_index)));
+        }
+    }
+
+    Ok(())
+}
+
+// Data is on disk once this returns, so the catalog can refer to it
+fn save_data<T: Serialize>(path : &String, data : &T) -> error::Result<()> {
+    let mut file = File::create(path)?;
+
+    let bytes:Vec<u8> = serialize(data, Infinite)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn read_bytes(path : &String) -> error::Result<Vec<u8>> {
+    let file = File::open(path)?;
     let mut buf_reader = BufReader::new(file);
     let mut buf: Vec<u8> = Vec::new();
-    buf_reader.read_to_end(&mut buf).unwrap();
+    buf_reader.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+// Stored data which can't be deserialized is damaged, so it's reported as an IO failure rather than a bad request
+fn read_data<T: DeserializeOwned>(path : &String) -> error::Result<T> {
+    let buf = read_bytes(path)?;
 
-    deserialize(&buf[..]).unwrap()
+    deserialize(&buf[..]).map_err(|e| error::Error::Io(io::Error::new(io::ErrorKind::InvalidData, format!("{} is damaged: {}", path, e))))
+}
+
+fn read_block(path : &String) -> error::Result<Block> {
+    println!("Reading block {}", path);
+
+    read_data(path)
 }
 
 
 impl Manager {
     pub fn new(db_home:String) -> Manager {
-        Manager { db_home: db_home, catalog: Catalog::new(), current_partition: Partition::new() }
+        Manager { db_home: db_home, catalog: Catalog::new(), current_partition: create_in_mem_partition(), purge_pending: false }
     }
 
-    pub fn add_column(&mut self, data_type: BlockType, name: String) {
+    // Logged inserts can be replayed only if the stored catalog knows their columns
+    pub fn add_column(&mut self, data_type: BlockType, name: String) -> error::Result<()> {
+        println!("Adding column <{}> of type {:?}", name, data_type);
+        self.catalog.add_column(data_type, name);
         ensure_partition_is_current(&self.catalog, &mut self.current_partition);
 
-        println!("Adding column <{}> of type {:?}", name, data_type);
-        let col = self.catalog.add_column(data_type, name);
-        self.current_partition.blocks.push(Block::create_block(&col.data_type));
+        self.store_catalog()
+    }
+
+    // Only the catalog is changed right away, the stored blocks are removed by purge_dropped_blocks
+    pub fn drop_column(&mut self, col_index: u32) -> error::Result<()> {
+        if self.catalog.column(col_index).is_none() {
+            return Err(error::Error::UnknownColumn(col_index));
+        }
+
+        if col_index == 0 {
+            return Err(error::Error::BadPayload(String::from("The ts column can't be dropped")));
+        }
+
+        println!("Dropping column {}", col_index);
+        self.catalog.drop_column(col_index);
+
+        if let Some(block) = self.current_partition.blocks.get_mut(col_index as usize) {
+            *block = Block::create_block(&block.data_type());
+        }
+
+        self.store_catalog()?;
+        self.purge_pending = true;
+
+        Ok(())
+    }
+
+    pub fn rename_column(&mut self, col_index: u32, name: String) -> error::Result<()> {
+        if self.catalog.column(col_index).is_none() {
+            return Err(error::Error::UnknownColumn(col_index));
+        }
+
+        match self.catalog.column_index(&name) {
+            Some(other_index) if other_index != col_index => return Err(error::Error::BadPayload(format!("Column <{}> already exists", name))),
+            _ => ()
+        }
+
+        println!("Renaming column {} to <{}>", col_index, name);
+        self.catalog.rename_column(col_index, name);
+
+        self.store_catalog()
     }
 
-    pub fn find_partition_info(&self, partition_id: u64) -> PartitionInfo {
+    pub fn purge_dropped_blocks(&mut self) -> error::Result<()> {
+        if !self.purge_pending {
+            return Ok(());
+        }
+
         for part in &self.catalog.available_partitions {
-            if part.id == partition_id {
-                return part.to_owned();
+            for col_index in &self.catalog.dropped_columns {
+                let block_path = format!("{}/block_{}.bin", part.location, col_index);
+
+                if Path::new(&block_path).exists() {
+                    println!("Removing block of dropped column: {}", block_path);
+                    fs::remove_file(&block_path)?;
+                }
             }
         }
 
-        println!("Could not find partition_id {}", partition_id);
+        self.purge_pending = false;
+        Ok(())
+    }
+
+    // All blocks are converted and written aside first, the change is committed by storing the catalog
+    pub fn alter_column_type(&mut self, col_index: u32, data_type: BlockType) -> error::Result<()> {
+        let current_type = match self.catalog.column(col_index) {
+            Some(column) => column.data_type.to_owned(),
+            None => return Err(error::Error::UnknownColumn(col_index))
+        };
+
+        if !current_type.can_widen_to(&data_type) {
+            return Err(error::Error::TypeMismatch(format!("Column {} of type {:?} can't be widened to {:?}", col_index, current_type, data_type)));
+        }
+
+        println!("Altering type of column {} from {:?} to {:?}", col_index, current_type, data_type);
 
-        // FIXME -> RESULT
-//        fail!("NOPE");
-        let pi = PartitionInfo{
-            min_ts: 0,
-            max_ts: 0,
-            id: 0,
-            location: String::from("dupa")
+        let in_mem_block = match self.current_partition.blocks.get(col_index as usize) {
+            Some(block) => match block.widen(&data_type, self.current_partition.blocks[0].len()) {
+                Some(widened) => Some(widened),
+                None => return Err(error::Error::BadPayload(format!("Column {} is not set in all rows, so it can't be dense", col_index)))
+            },
+            None => None
         };
-        pi
+
+        let mut tmp_paths:Vec<(String, String)> = Vec::new();
+        let written = self.write_widened_blocks(col_index, &data_type, &mut tmp_paths);
+
+        let committed = written.and_then(|_| {
+            self.catalog.columns[col_index as usize].data_type = data_type.to_owned();
+            self.store_catalog()
+        });
+
+        if let Err(e) = committed {
+            self.catalog.columns[col_index as usize].data_type = current_type;
+            for &(ref tmp_path, _) in &tmp_paths {
+                fs::remove_file(tmp_path).ok();
+            }
+            return Err(e);
+        }
+
+        if let Some(widened) = in_mem_block {
+            self.current_partition.blocks[col_index as usize] = widened;
+        }
+
+        // The change is committed already - until renamed (at latest on next start), the old blocks are widened when loaded
+        for (tmp_path, block_path) in tmp_paths {
+            if let Err(e) = fs::rename(&tmp_path, &block_path) {
+                println!("Unable to replace {} with the widened block: {}", block_path, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Widened blocks are left aside when the process dies during alter_column_type
+    fn finish_column_alterations(&self) {
+        for part in &self.catalog.available_partitions {
+            let entries = match fs::read_dir(&part.location) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    println!("Unable to list partition {}: {}", part.location, e);
+                    continue;
+                }
+            };
+
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                if !file_name.starts_with("block_") || !file_name.ends_with(".bin.tmp") {
+                    continue;
+                }
+
+                let col_index = match file_name["block_".len()..file_name.len() - ".bin.tmp".len()].parse::<u32>() {
+                    Ok(col_index) => col_index,
+                    Err(_) => continue
+                };
+
+                let tmp_path = format!("{}/{}", part.location, file_name);
+                let block_path = format!("{}/block_{}.bin", part.location, col_index);
+
+                // Only the blocks of the type committed to the catalog are complete
+                let committed = match (read_block(&tmp_path), self.catalog.column(col_index)) {
+                    (Ok(block), Some(column)) => block.data_type() == column.data_type,
+                    _ => false
+                };
+
+                let finished = if committed {
+                    println!("Replacing {} with the widened block", block_path);
+                    fs::rename(&tmp_path, &block_path)
+                } else {
+                    println!("Removing widened block of an unfinished change: {}", tmp_path);
+                    fs::remove_file(&tmp_path)
+                };
+
+                if let Err(e) = finished {
+                    println!("Unable to finish change of {}: {}", block_path, e);
+                }
+            }
+        }
+    }
+
+    fn write_widened_blocks(&self, col_index: u32, data_type: &BlockType, tmp_paths: &mut Vec<(String, String)>) -> error::Result<()> {
+        for part in &self.catalog.available_partitions {
+            let block_path = format!("{}/block_{}.bin", part.location, col_index);
+            let row_count = self.load_block(part, 0)?.len();
+
+            let block = if Path::new(&block_path).exists() {
+                read_block(&block_path)?
+            } else if *data_type == BlockType::Int64Dense && row_count > 0 {
+                return Err(error::Error::BadPayload(format!("Column {} is not set in partition {}, so it can't be dense", col_index, part.id)));
+            } else {
+                continue;
+            };
+
+            let widened = match block.widen(data_type, row_count) {
+                Some(widened) => widened,
+                None => return Err(error::Error::BadPayload(format!("Column {} is not set in all rows of partition {}, so it can't be dense", col_index, part.id)))
+            };
+
+            let tmp_path = format!("{}.tmp", block_path);
+            tmp_paths.push((tmp_path.to_owned(), block_path));
+            save_data(&tmp_path, &widened)?;
+        }
+
+        Ok(())
     }
 
-    pub fn insert(&mut self, msg : &InsertMessage) {
+    pub fn find_partition_info(&self, partition_id: u64) -> error::Result<PartitionInfo> {
+        match self.catalog.find_partition(partition_id) {
+            Some(part) => Ok(part.to_owned()),
+            None => Err(error::Error::UnknownPartition(partition_id))
+        }
+    }
+
+    // Describes the not yet flushed partition, so it can be scanned the same way as the stored ones
+    pub fn in_mem_partition_info(&self) -> Option<PartitionInfo> {
+        let mut min_ts = u64::max_value();
+        let mut max_ts = 0;
+
+        match self.current_partition.blocks.first() {
+            Some(&Block::Int64Dense(ref b)) if !b.data.is_empty() => for v in &b.data {
+                min_ts = cmp::min(min_ts, *v);
+                max_ts = cmp::max(max_ts, *v);
+            },
+            _ => return None
+        }
+
+        Some(PartitionInfo {
+            min_ts: min_ts,
+            max_ts: max_ts,
+            id: self.current_partition.metadata.id,
+            location: String::new(),
+            block_stats: Vec::new()
+        })
+    }
+
+    pub fn insert(&mut self, msg : &InsertMessage) -> error::Result<()> {
         println!("Inserting a message of {} records", msg.row_count);
 
-        // TODO: validate columns - their types and if they exist
+        // Nothing is written (not even to the log) unless the whole message is valid
+        self.validate_insert(msg)?;
 
-        // TODO: for sparse sets we could add assertion that order of offsets is monotonically growing
+        // The message must be durable before it is acknowledged
+        self.append_to_wal(msg)?;
+        self.apply_insert(msg);
 
-        ensure_partition_is_current(&self.catalog, &mut self.current_partition);
+        if self.current_partition.blocks[0].len() > 200000 {
+            self.dump_in_mem_partition()?;
+        }
 
-        let current_offset = self.current_partition.blocks[0].len();
+        Ok(())
+    }
 
-        for col_no in 0..msg.col_count {
-            let catalog_col_no = msg.col_types[col_no as usize].0;
-            let input_block = &msg.blocks[col_no as usize];
-            let output_block = &mut self.current_partition.blocks[catalog_col_no as usize];
+    pub fn validate_insert(&self, msg : &InsertMessage) -> error::Result<()> {
+        validate_insert(&self.catalog, msg)
+    }
 
-            match input_block  {
-                &Block::Int64Dense(ref in_block) => {
-                    match output_block {
-                        &mut Block::Int64Dense(ref mut out_block) => {
-                            assert_eq!(in_block.data.len(), msg.row_count as usize);
-                            out_block.data.extend(&in_block.data);
-                        },
-                        _ => panic!("Non matching blocks")
-                    }
-                },
-                &Block::Int64Sparse(ref in_block) => {
-                    match output_block {
-                        &mut Block::Int64Sparse(ref mut out_block) => {
-                            for pair in &in_block.data {
-                                assert!(pair.0 < msg.row_count);
-
-                                out_block.data.push((pair.0 + current_offset as u32, pair.1));
-                            }
-                        },
-                        _ => panic!("Non matching blocks")
-                    }
+    // Creates the missing columns (when allowed) and inserts the message like any other
+    pub fn insert_named(&mut self, msg : &NamedInsertMessage) -> error::Result<NamedInsertResponse> {
+        // Columns are added to a copy first, so nothing changes unless the whole message is valid
+        let mut catalog = self.catalog.to_owned();
+        let mut columns:Vec<(String, u32)> = Vec::new();
+        let mut added_columns:Vec<(String, BlockType)> = Vec::new();
+
+        for &(ref name, ref col_type) in &msg.col_types {
+            let col_index = match catalog.column_index(name) {
+                Some(col_index) => col_index,
+                None if msg.create_missing_columns => {
+                    catalog.add_column(col_type.to_owned(), name.to_owned());
+                    added_columns.push((name.to_owned(), col_type.to_owned()));
+                    catalog.columns.len() as u32 - 1
                 },
-                &Block::Int32Sparse(ref in_block) => {
-                    match output_block {
-                        &mut Block::Int32Sparse(ref mut out_block) => {
-                            for pair in &in_block.data {
-                                assert!(pair.0 < msg.row_count);
-
-                                out_block.data.push((pair.0 + current_offset as u32, pair.1));
-                            }
-                        },
-                        _ => panic!("Non matching blocks")
-                    }
-                },
-                &Block::Int16Sparse(ref in_block) => {
-                    match output_block {
-                        &mut Block::Int16Sparse(ref mut out_block) => {
-                            for pair in &in_block.data {
-                                assert!(pair.0 < msg.row_count);
-
-                                out_block.data.push((pair.0 + current_offset as u32, pair.1));
-                            }
-                        },
-                        _ => panic!("Non matching blocks")
-                    }
-                },
-                &Block::Int8Sparse(ref in_block) => {
-                    match output_block {
-                        &mut Block::Int8Sparse(ref mut out_block) => {
-                            for pair in &in_block.data {
-                                assert!(pair.0 < msg.row_count);
-
-                                out_block.data.push((pair.0 + current_offset as u32, pair.1));
-                            }
-                        },
-                        _ => panic!("Non matching blocks")
-                    }
-                },
-                &Block::StringBlock(ref in_block) => {
-                    match output_block {
-                        &mut Block::StringBlock(ref mut out_block) => {
-                            for (index, pair) in in_block.index_data.iter().enumerate() {
-                                assert!(pair.0 < msg.row_count);
+                None => return Err(error::Error::UnknownColumnName(name.to_owned()))
+            };
+
+            columns.push((name.to_owned(), col_index));
+        }
+
+        let indexed_msg = InsertMessage {
+            row_count: msg.row_count,
+            col_count: msg.col_count,
+            col_types: columns.iter().zip(msg.col_types.iter()).map(|(&(_, col_index), &(_, ref col_type))| (col_index, col_type.to_owned())).collect(),
+            blocks: msg.blocks.to_owned()
+        };
+
+        validate_insert(&catalog, &indexed_msg)?;
+
+        for (name, col_type) in added_columns {
+            self.add_column(col_type, name)?;
+        }
+
+        self.insert(&indexed_msg)?;
+
+        Ok(NamedInsertResponse { columns: columns })
+    }
+
+    // The message must be already validated
+    fn apply_insert(&mut self, msg : &InsertMessage) {
+        ensure_partition_is_current(&self.catalog, &mut self.current_partition);
+
+        let current_offset = self.current_partition.blocks[0].len() as u32;
+
+        for (&(col_index, _), input_block) in msg.col_types.iter().zip(msg.blocks.iter()) {
+            self.current_partition.blocks[col_index as usize].append_block(input_block, current_offset);
+        }
+    }
+
+    fn append_to_wal(&self, msg : &InsertMessage) -> error::Result<()> {
+        fs::create_dir_all(&self.db_home)?;
+
+        let mut file = OpenOptions::new().create(true).append(true).open(self.wal_path())?;
+        let bytes:Vec<u8> = serialize(msg, Infinite)?;
+        file.write_all(&bytes)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    // Puts all inserts which were not flushed yet back to the in memory partition
+    fn replay_wal(&mut self) -> error::Result<()> {
+        if !Path::new(&self.wal_path()).exists() {
+            return Ok(());
+        }
 
-                                let offset = pair.0;
-                                let position = pair.1;
+        let mut buf: Vec<u8> = Vec::new();
+        File::open(self.wal_path())?.read_to_end(&mut buf)?;
 
-                                out_block.index_data.push((pair.0 + current_offset as u32, out_block.str_data.len()));
+        let mut reader = Cursor::new(&buf[..]);
+        let mut replayed_count = 0;
 
-                                let end_position = if index < in_block.index_data.len()-1 {
-                                    in_block.index_data[index+1].1
-                                } else {
-                                    in_block.str_data.len()
-                                };
+        while (reader.position() as usize) < buf.len() {
+            let valid_position = reader.position();
 
-                                out_block.str_data.extend(&in_block.str_data[position..end_position])
-                            }
+            match deserialize_from::<_, InsertMessage, _>(&mut reader, Infinite) {
+                Ok(mut msg) => {
+                    self.conform_to_catalog(&mut msg);
+
+                    match self.validate_insert(&msg) {
+                        Ok(()) => {
+                            self.apply_insert(&msg);
+                            replayed_count += 1;
                         },
-                        _ => {
-                            panic!("Non matching blocks");
-                        }
+                        Err(e) => println!("Skipping entry at {} of write-ahead log: {}", valid_position, e)
                     }
                 },
-           }
+                Err(_) => {
+                    // Most likely the process died while writing it, so it was never acknowledged
+                    println!("Dropping partially written entry at {} of write-ahead log", valid_position);
+                    OpenOptions::new().write(true).open(self.wal_path())?.set_len(valid_position)?;
+                    break;
+                }
+            }
         }
 
-        if self.current_partition.blocks[0].len() > 200000 {
-            self.dump_in_mem_partition();
+        println!("Replayed {} inserts from write-ahead log", replayed_count);
+        Ok(())
+    }
+
+    // Logged inserts might still have data of columns dropped or altered later on
+    fn conform_to_catalog(&self, msg : &mut InsertMessage) {
+        let mut col_no = 0;
+
+        while col_no < cmp::min(msg.col_types.len(), msg.blocks.len()) {
+            let col_index = msg.col_types[col_no].0;
+
+            if self.catalog.is_dropped(col_index) {
+                msg.col_types.remove(col_no);
+                msg.blocks.remove(col_no);
+                msg.col_count = msg.col_count.saturating_sub(1);
+                continue;
+            }
+
+            if let Some(column) = self.catalog.column(col_index) {
+                if let Some(widened) = msg.blocks[col_no].widen(&column.data_type, msg.row_count as usize) {
+                    msg.col_types[col_no].1 = column.data_type.to_owned();
+                    msg.blocks[col_no] = widened;
+                }
+            }
+
+            col_no += 1;
+        }
+
+        // Dense columns added after the insert was logged got zeros in its rows
+        for (col_index, column) in self.catalog.live_columns() {
+            if column.data_type.is_dense() && !msg.col_types.iter().any(|&(logged_index, _)| logged_index == col_index) {
+                msg.col_types.push((col_index, column.data_type.to_owned()));
+                msg.blocks.push(Block::create_block_for_rows(&column.data_type, msg.row_count as usize));
+                msg.col_count += 1;
+            }
+        }
+    }
+
+    // Log of a previous generation is left behind when the process dies right after the flush was committed
+    fn remove_flushed_wal(&self, wal_generation : u64) {
+        let wal_path = self.wal_path_of(wal_generation);
+
+        if Path::new(&wal_path).exists() {
+            println!("Removing flushed write-ahead log {}", wal_path);
+            if let Err(e) = fs::remove_file(&wal_path) {
+                println!("Removing flushed write-ahead log failed: {}", e);
+            }
         }
     }
 
@@ -241,6 +602,14 @@ impl Manager {
         catalog_file_name
     }
 
+    pub fn wal_path(&self) -> String {
+        self.wal_path_of(self.catalog.wal_generation)
+    }
+
+    fn wal_path_of(&self, wal_generation : u64) -> String {
+        format!("{}/wal_{}.bin", self.db_home, wal_generation)
+    }
+
     pub fn partition_path(&self, metadata : &PartitionMetadata) -> String {
         let mut partition_file_name = self.db_home.to_owned() + "/partitions/";
 
@@ -256,34 +625,50 @@ impl Manager {
         partition_file_name
     }
 
-    pub fn reload_catalog(&mut self) {
+    pub fn reload_catalog(&mut self) -> error::Result<()> {
         if Path::new(&self.catalog_path()).exists() {
-            let file = File::open(self.catalog_path()).unwrap();
-            let mut buf_reader = BufReader::new(file);
-            let mut buf: Vec<u8> = Vec::new();
-            buf_reader.read_to_end(&mut buf).unwrap();
+            self.catalog = Catalog::from_bytes(&read_bytes(&self.catalog_path())?)?;
+            self.catalog.rebuild_partition_index();
+            self.purge_pending = !self.catalog.dropped_columns.is_empty();
 
-            self.catalog = deserialize(&buf[..]).unwrap();
+            if self.catalog.wal_generation > 0 {
+                self.remove_flushed_wal(self.catalog.wal_generation - 1);
+            }
+            self.finish_column_alterations();
+            self.replay_wal()
         } else {
             println!("Catalog does not exist. Skipping loading it.");
+            Ok(())
         }
     }
 
-    pub fn store_catalog(&self) {
+    pub fn store_catalog(&self) -> error::Result<()> {
+        self.write_catalog(&self.catalog)
+    }
+
+    // Changed catalog can be stored before it replaces the current one
+    fn write_catalog(&self, catalog : &Catalog) -> error::Result<()> {
         println!("Saving catalog");
-        fs::create_dir_all(&self.db_home);
+        fs::create_dir_all(&self.db_home)?;
+
+        // Written aside and renamed, so a crash never leaves a partially written catalog
+        let tmp_path = format!("{}.tmp", self.catalog_path());
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&catalog.to_bytes()?)?;
+        file.sync_all()?;
 
-        save_data(&self.catalog_path(), &self.catalog);
+        fs::rename(&tmp_path, self.catalog_path())?;
+        Ok(())
     }
 
-    pub fn store_partition(&self, part : &Partition) -> String {
+    pub fn store_partition(&self, part : &Partition) -> error::Result<String> {
         let part_path = self.partition_path(&part.metadata);
 
-        fs::create_dir_all(&part_path);
+        fs::create_dir_all(&part_path)?;
 
-        save_data(&format!("{}/metadata.bin", part_path), &part.metadata);
+        save_data(&format!("{}/metadata.bin", part_path), &part.metadata)?;
         for block_index in &part.metadata.existing_blocks {
-            save_data(&format!("{}/block_{}.bin", part_path, block_index), &part.blocks[*block_index as usize]);
+            save_data(&format!("{}/block_{}.bin", part_path, block_index), &part.blocks[*block_index as usize])?;
         }
 
         // metadata
@@ -291,48 +676,98 @@ impl Manager {
 
         println!("Saved partition: {}", part_path);
 
-        part_path
+        Ok(part_path)
     }
 
-    pub fn save_block(&self, pinfo : &PartitionInfo, block : &Block, block_index : u32) {
+    pub fn save_block(&self, pinfo : &PartitionInfo, block : &Block, block_index : u32) -> error::Result<()> {
         let part_path = &pinfo.location;
         let block_path = format!("{}/block_{}.bin", part_path, block_index);
 
-        save_data(&block_path, block);
+        save_data(&block_path, block)
     }
 
-    pub fn load_block(&self, pinfo : &PartitionInfo, block_index : u32) -> Block {
+    // Keeps the zone map of a modified stored block current, the catalog still needs to be stored afterwards
+    pub fn update_block_stats(&mut self, partition_id : u64, block_index : u32, block : &Block) -> error::Result<()> {
+        match self.catalog.find_partition_mut(partition_id) {
+            Some(part_info) => {
+                part_info.update_block_stats(block_index, block);
+                Ok(())
+            },
+            None => Err(error::Error::UnknownPartition(partition_id))
+        }
+    }
+
+    pub fn load_block(&self, pinfo : &PartitionInfo, block_index : u32) -> error::Result<Block> {
+        if pinfo.id == self.current_partition.metadata.id {
+            // In memory partition - copying is still much cheaper than reading it from disk would be
+            return Ok(match self.current_partition.blocks.get(block_index as usize) {
+                Some(block) => block.to_owned(),
+                None => Block::create_block(&self.catalog.columns[block_index as usize].data_type)
+            });
+        }
+
         let part_path = &pinfo.location;
         let block_path = format!("{}/block_{}.bin", part_path, block_index);
 
         if Path::new(&block_path).exists() {
-            read_block(&block_path)
+            let block = read_block(&block_path)?;
+            let data_type = &self.catalog.columns[block_index as usize].data_type;
+
+            // Column was altered, but the process died before the block was replaced with the widened one
+            if block.data_type() != *data_type {
+                let row_count = block.len();
+                return match block.widen(data_type, row_count) {
+                    Some(widened) => Ok(widened),
+                    None => Err(error::Error::Io(io::Error::new(io::ErrorKind::InvalidData, format!("{} of type {:?} can't be converted to {:?}", block_path, block.data_type(), data_type))))
+                };
+            }
+
+            Ok(block)
         } else {
             // Lets return empty block (which should be the same as if the block does not exist)
             let data_type = &self.catalog.columns[block_index as usize].data_type;
-            Block::create_block(data_type)
+            if !data_type.is_dense() || block_index == 0 {
+                return Ok(Block::create_block(data_type));
+            }
+
+            // Dense column added after the partition was stored has a zero in each row
+            let row_count = match pinfo.row_count() {
+                Some(row_count) => row_count as usize,
+                None => self.load_block(pinfo, 0)?.len()
+            };
+            Ok(Block::create_block_for_rows(data_type, row_count))
         }
     }
 
-    pub fn dump_in_mem_partition(&mut self) {
+    pub fn dump_in_mem_partition(&mut self) -> error::Result<()> {
         if self.current_partition.blocks.is_empty() {
             println!("Cannot dump empty partition");
-            return
+            return Ok(())
         }
 
         println!("Dumping in memory partition having {} records", self.current_partition.blocks[0].len());
         self.current_partition.prepare();
-        let stored_path = self.store_partition(&self.current_partition);
-        self.catalog.available_partitions.push(PartitionInfo {
+        let stored_path = self.store_partition(&self.current_partition)?;
+
+        // Nothing changes until the catalog is stored, so a failed flush leaves the rows in memory and logged
+        let mut catalog = self.catalog.to_owned();
+        catalog.add_partition(PartitionInfo {
             min_ts: self.current_partition.metadata.min_ts,
             max_ts: self.current_partition.metadata.max_ts,
             id: self.current_partition.metadata.id,
-            location: stored_path
+            location: stored_path,
+            block_stats: self.current_partition.metadata.block_stats.to_owned()
         });
+        catalog.wal_generation += 1;
+        self.write_catalog(&catalog)?;
 
-        self.store_catalog();
+        // Once the catalog is stored, the log is not replayed anymore (even if removing it fails)
+        let flushed_generation = self.catalog.wal_generation;
+        self.catalog = catalog;
+        self.remove_flushed_wal(flushed_generation);
 
-        self.current_partition = Partition::new();
+        self.current_partition = create_in_mem_partition();
+        Ok(())
     }
 
 }
@@ -348,7 +783,7 @@ fn create_test_catalog<'a>() -> Manager {
     manager.catalog.add_column(BlockType::Int32Sparse, String::from("int_02"));
     manager.catalog.add_column(BlockType::String, String::from("str"));
 
-    manager.store_catalog();
+    manager.store_catalog().unwrap();
 
     manager
 }
@@ -408,7 +843,7 @@ fn it_inserts_and_dumps_and_compacts_data_smoke_test() {
     manager.catalog.add_column(BlockType::String, String::from("p2"));
     manager.catalog.add_column(BlockType::String, String::from("p3"));
 
-    manager.store_catalog();
+    manager.store_catalog().unwrap();
 
     let base_ts = 1495493600 as u64 * 1000000;
     let insert_msg = InsertMessage {
@@ -440,23 +875,27 @@ fn it_inserts_and_dumps_and_compacts_data_smoke_test() {
         ]
     };
 
-    manager.insert(&insert_msg);
-    manager.dump_in_mem_partition();
+    manager.insert(&insert_msg).unwrap();
+    manager.dump_in_mem_partition().unwrap();
 
-    let part_info = &manager.catalog.available_partitions[0];
+    let part_info = &manager.catalog.available_partitions[0].to_owned();
 
     let filter = vec![
         ScanFilter {
             column: 2,
             op: ScanComparison::Eq,
             val: 100,
-            str_val: vec![]
+            str_val: vec![],
+            vals: vec![],
+            str_vals: vec![]
         },
         ScanFilter {
             column: 3,
             op: ScanComparison::Eq,
             val: 0,
-            str_val: "foo".as_bytes().to_vec()
+            str_val: "foo".as_bytes().to_vec(),
+            vals: vec![],
+            str_vals: vec![]
         }
     ];
 
@@ -476,7 +915,7 @@ fn it_inserts_and_dumps_and_compacts_data_smoke_test() {
         }
     };
 
-    handle_data_compaction(&manager, &req);
+    handle_data_compaction(&mut manager, &req).unwrap();
 
 
     // Now we need to scan and see if anything was changed
@@ -484,7 +923,7 @@ fn it_inserts_and_dumps_and_compacts_data_smoke_test() {
         Block::Int32Sparse(Int32SparseBlock{
             data: vec![(0, 100), (1, 101), (2, 100), (3, 101)]
         }),
-        manager.load_block(part_info, 2)
+        manager.load_block(part_info, 2).unwrap()
     );
 
     assert_eq!(
@@ -492,7 +931,7 @@ fn it_inserts_and_dumps_and_compacts_data_smoke_test() {
             index_data: vec![(2,0)],
             str_data: "bar".as_bytes().to_vec()
         }),
-        manager.load_block(part_info, 3)
+        manager.load_block(part_info, 3).unwrap()
     );
 
     assert_eq!(
@@ -500,7 +939,7 @@ fn it_inserts_and_dumps_and_compacts_data_smoke_test() {
             index_data: vec![(0,0),(2,1)],
             str_data: "ac".as_bytes().to_vec()
         }),
-        manager.load_block(part_info, 4)
+        manager.load_block(part_info, 4).unwrap()
     );
 
     assert_eq!(
@@ -508,6 +947,477 @@ fn it_inserts_and_dumps_and_compacts_data_smoke_test() {
             index_data: vec![(1,0),(3,1)],
             str_data: "bd".as_bytes().to_vec()
         }),
-        manager.load_block(part_info, 5)
+        manager.load_block(part_info, 5).unwrap()
     );
 }
+
+#[test]
+fn it_replays_write_ahead_log() {
+    let db_home = "/tmp/hyena_test_wal";
+    fs::remove_dir_all(db_home).ok();
+
+    let mut manager = Manager::new(String::from(db_home));
+    manager.catalog.add_column(BlockType::Int64Dense, String::from("ts"));
+    manager.catalog.add_column(BlockType::Int64Sparse, String::from("int_01"));
+    manager.store_catalog().unwrap();
+
+    let base_ts = 1495493600 as u64 * 1000000;
+    for i in 0..2 {
+        manager.insert(&InsertMessage {
+            row_count: 2,
+            col_count: 2,
+            col_types: vec![(0, BlockType::Int64Dense), (1, BlockType::Int64Sparse)],
+            blocks: vec![
+                Block::Int64Dense(Int64DenseBlock{
+                    data: vec![base_ts + i*2000, base_ts + i*2000 + 1000]
+                }),
+                Block::Int64Sparse(Int64SparseBlock{
+                    data: vec![(1, 100 + i)]
+                })
+            ]
+        }).unwrap();
+    }
+
+    // Simulate a crash in the middle of writing next entry
+    let wal_len = fs::metadata(manager.wal_path()).unwrap().len();
+    OpenOptions::new().append(true).open(manager.wal_path()).unwrap().write_all(&[1, 2, 3]).unwrap();
+
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+
+    assert_eq!(manager.current_partition.blocks, restarted.current_partition.blocks);
+    assert_eq!(wal_len, fs::metadata(restarted.wal_path()).unwrap().len());
+
+    let flushed_wal_path = restarted.wal_path();
+    restarted.dump_in_mem_partition().unwrap();
+    assert!(!Path::new(&flushed_wal_path).exists());
+    assert_eq!(1, restarted.catalog.available_partitions.len());
+}
+
+#[test]
+fn it_does_not_replay_flushed_inserts() {
+    let db_home = "/tmp/hyena_test_wal_flushed";
+    fs::remove_dir_all(db_home).ok();
+
+    let mut manager = Manager::new(String::from(db_home));
+    manager.catalog.add_column(BlockType::Int64Dense, String::from("ts"));
+    manager.add_column(BlockType::Int64Sparse, String::from("int_01")).unwrap();
+
+    manager.insert(&InsertMessage {
+        row_count: 1,
+        col_count: 2,
+        col_types: vec![(0, BlockType::Int64Dense), (1, BlockType::Int64Sparse)],
+        blocks: vec![
+            Block::Int64Dense(Int64DenseBlock{ data: vec![1495493600 * 1000000] }),
+            Block::Int64Sparse(Int64SparseBlock{ data: vec![(0, 100)] })
+        ]
+    }).unwrap();
+
+    // The column is known after a crash, so its logged values are not dropped
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+    assert_eq!(manager.current_partition.blocks, restarted.current_partition.blocks);
+
+    // Simulate a crash after the catalog was stored, but before the log was removed
+    let flushed_wal_path = manager.wal_path();
+    let mut wal = Vec::new();
+    File::open(&flushed_wal_path).unwrap().read_to_end(&mut wal).unwrap();
+    manager.dump_in_mem_partition().unwrap();
+    File::create(&flushed_wal_path).unwrap().write_all(&wal).unwrap();
+
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+    assert_eq!(1, restarted.catalog.available_partitions.len());
+    assert!(restarted.current_partition.blocks.iter().all(|block| block.len() == 0));
+    assert!(!Path::new(&flushed_wal_path).exists());
+
+    // Failed flush keeps the catalog, so the rows are neither in a partition twice nor lost
+    let mut manager = restarted;
+    let row = InsertMessage {
+        row_count: 1,
+        col_count: 1,
+        col_types: vec![(0, BlockType::Int64Dense)],
+        blocks: vec![Block::Int64Dense(Int64DenseBlock{ data: vec![1495493700 * 1000000] })]
+    };
+    manager.insert(&row).unwrap();
+    let catalog = manager.catalog.to_owned();
+    fs::remove_file(manager.catalog_path()).unwrap();
+    fs::create_dir(manager.catalog_path()).unwrap();
+    assert_eq!(6, manager.dump_in_mem_partition().unwrap_err().status());
+    assert_eq!(catalog, manager.catalog);
+    assert_eq!(1, manager.current_partition.blocks[0].len());
+
+    fs::remove_dir(manager.catalog_path()).unwrap();
+    manager.store_catalog().unwrap();
+    manager.insert(&row).unwrap();
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+    assert_eq!(1, restarted.catalog.available_partitions.len());
+    assert_eq!(2, restarted.current_partition.blocks[0].len());
+}
+
+#[test]
+fn it_finds_partitions_after_reload() {
+    let db_home = "/tmp/hyena_test_find_partition";
+    fs::remove_dir_all(db_home).ok();
+
+    let mut manager = Manager::new(String::from(db_home));
+    manager.catalog.add_column(BlockType::Int64Dense, String::from("ts"));
+    manager.insert(&InsertMessage {
+        row_count: 1,
+        col_count: 1,
+        col_types: vec![(0, BlockType::Int64Dense)],
+        blocks: vec![Block::Int64Dense(Int64DenseBlock{ data: vec![1495493600 * 1000000] })]
+    }).unwrap();
+    manager.dump_in_mem_partition().unwrap();
+
+    let part_id = manager.catalog.available_partitions[0].id;
+
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+
+    assert_eq!(manager.catalog.available_partitions[0], restarted.find_partition_info(part_id).unwrap());
+    assert_eq!(4, restarted.find_partition_info(part_id + 1).unwrap_err().status());
+}
+
+#[test]
+fn it_rejects_invalid_inserts() {
+    let db_home = "/tmp/hyena_test_invalid_inserts";
+    fs::remove_dir_all(db_home).ok();
+
+    let mut manager = Manager::new(String::from(db_home));
+    manager.catalog.add_column(BlockType::Int64Dense, String::from("ts"));
+    manager.catalog.add_column(BlockType::Int32Sparse, String::from("pattern_id"));
+    manager.catalog.add_column(BlockType::String, String::from("p1"));
+
+    let valid_msg = InsertMessage {
+        row_count: 2,
+        col_count: 3,
+        col_types: vec![(0, BlockType::Int64Dense), (1, BlockType::Int32Sparse), (2, BlockType::String)],
+        blocks: vec![
+            Block::Int64Dense(Int64DenseBlock{ data: vec![1000, 2000] }),
+            Block::Int32Sparse(Int32SparseBlock{ data: vec![(0, 5), (1, 7)] }),
+            Block::StringBlock(StringBlock{ index_data: vec![(1, 0)], str_data: "foo".as_bytes().to_vec() })
+        ]
+    };
+
+    let mut unknown_column = valid_msg.clone();
+    unknown_column.col_types[1].0 = 3;
+
+    let mut wrong_type = valid_msg.clone();
+    wrong_type.col_types[1].1 = BlockType::Int64Sparse;
+    wrong_type.blocks[1] = Block::Int64Sparse(Int64SparseBlock{ data: vec![(0, 5)] });
+
+    let mut short_dense = valid_msg.clone();
+    short_dense.blocks[0] = Block::Int64Dense(Int64DenseBlock{ data: vec![1000] });
+
+    let mut offset_out_of_range = valid_msg.clone();
+    offset_out_of_range.blocks[1] = Block::Int32Sparse(Int32SparseBlock{ data: vec![(0, 5), (2, 7)] });
+
+    let mut offsets_not_increasing = valid_msg.clone();
+    offsets_not_increasing.blocks[1] = Block::Int32Sparse(Int32SparseBlock{ data: vec![(1, 5), (1, 7)] });
+
+    let mut string_out_of_range = valid_msg.clone();
+    string_out_of_range.blocks[2] = Block::StringBlock(StringBlock{ index_data: vec![(1, 4)], str_data: "foo".as_bytes().to_vec() });
+
+    let mut no_ts = valid_msg.clone();
+    no_ts.col_count = 1;
+    no_ts.col_types = vec![(1, BlockType::Int32Sparse)];
+    no_ts.blocks = vec![Block::Int32Sparse(Int32SparseBlock{ data: vec![(0, 5)] })];
+
+    let expected_statuses = vec![
+        (unknown_column, 2),
+        (wrong_type, 3),
+        (short_dense, 1),
+        (offset_out_of_range, 1),
+        (offsets_not_increasing, 1),
+        (string_out_of_range, 1),
+        (no_ts, 1)
+    ];
+
+    for (msg, status) in expected_statuses {
+        assert_eq!(status, manager.insert(&msg).unwrap_err().status());
+    }
+
+    // Nothing was applied or logged
+    assert!(manager.current_partition.blocks.iter().all(|block| block.len() == 0));
+    assert!(!Path::new(&manager.wal_path()).exists());
+
+    manager.insert(&valid_msg).unwrap();
+    assert_eq!(valid_msg.blocks, manager.current_partition.blocks);
+}
+
+#[test]
+fn it_keeps_dense_columns_aligned() {
+    let db_home = "/tmp/hyena_test_dense_aligned";
+    fs::remove_dir_all(db_home).ok();
+
+    let mut manager = Manager::new(String::from(db_home));
+    manager.catalog.add_column(BlockType::Int64Dense, String::from("ts"));
+    manager.catalog.add_column(BlockType::Int64Dense, String::from("source"));
+
+    let without_source = InsertMessage {
+        row_count: 2,
+        col_count: 1,
+        col_types: vec![(0, BlockType::Int64Dense)],
+        blocks: vec![Block::Int64Dense(Int64DenseBlock{ data: vec![1000, 2000] })]
+    };
+
+    let mut with_source = without_source.clone();
+    with_source.col_count = 2;
+    with_source.col_types.push((1, BlockType::Int64Dense));
+    with_source.blocks.push(Block::Int64Dense(Int64DenseBlock{ data: vec![7, 8] }));
+
+    manager.insert(&with_source).unwrap();
+    assert_eq!(1, manager.insert(&without_source).unwrap_err().status());
+    manager.insert(&with_source).unwrap();
+    assert_eq!(Block::Int64Dense(Int64DenseBlock{ data: vec![7, 8, 7, 8] }), manager.current_partition.blocks[1]);
+
+    // Rows inserted before the column was added get zeros
+    manager.add_column(BlockType::Float64Dense, String::from("ratio")).unwrap();
+    assert_eq!(4, manager.current_partition.blocks[2].len());
+    assert_eq!(1, manager.insert(&with_source).unwrap_err().status());
+
+    // Same for the logged inserts
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+    assert_eq!(manager.current_partition.blocks, restarted.current_partition.blocks);
+}
+
+#[test]
+fn it_inserts_by_column_name() {
+    use api::NamedInsertMessage;
+
+    let db_home = "/tmp/hyena_test_named_insert";
+    fs::remove_dir_all(db_home).ok();
+
+    let mut manager = Manager::new(String::from(db_home));
+    manager.catalog.add_column(BlockType::Int64Dense, String::from("ts"));
+    manager.catalog.add_column(BlockType::Int32Sparse, String::from("pattern_id"));
+
+    let mut msg = NamedInsertMessage {
+        row_count: 2,
+        col_count: 3,
+        col_types: vec![(String::from("pattern_id"), BlockType::Int32Sparse), (String::from("ts"), BlockType::Int64Dense), (String::from("p1"), BlockType::String)],
+        blocks: vec![
+            Block::Int32Sparse(Int32SparseBlock{ data: vec![(1, 5)] }),
+            Block::Int64Dense(Int64DenseBlock{ data: vec![1000, 2000] }),
+            Block::StringBlock(StringBlock{ index_data: vec![(0, 0)], str_data: "foo".as_bytes().to_vec() })
+        ],
+        create_missing_columns: false
+    };
+
+    assert_eq!(2, manager.insert_named(&msg).unwrap_err().status());
+    assert_eq!(2, manager.catalog.columns.len());
+
+    msg.create_missing_columns = true;
+    let resp = manager.insert_named(&msg).unwrap();
+
+    assert_eq!(vec![(String::from("pattern_id"), 1), (String::from("ts"), 0), (String::from("p1"), 2)], resp.columns);
+    assert_eq!(Some(2), manager.catalog.column_index(&String::from("p1")));
+    assert_eq!(msg.blocks[2], manager.current_partition.blocks[2]);
+
+    // The new column is known after restart, so the logged insert can be replayed
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+    assert_eq!(manager.current_partition.blocks, restarted.current_partition.blocks);
+}
+
+#[test]
+fn it_drops_and_renames_columns() {
+    use api::{create_test_manager, RefreshCatalogResponse};
+
+    let db_home = "/tmp/hyena_test_drop_column";
+    let mut manager = create_test_manager(db_home);
+    let part_info = manager.catalog.available_partitions[0].to_owned();
+    let block_path = format!("{}/block_2.bin", part_info.location);
+
+    assert_eq!(1, manager.drop_column(0).unwrap_err().status());
+    manager.drop_column(2).unwrap();
+    assert_eq!(2, manager.drop_column(2).unwrap_err().status());
+
+    // Data is removed only once purged
+    assert!(Path::new(&block_path).exists());
+    manager.purge_dropped_blocks().unwrap();
+    assert!(!Path::new(&block_path).exists());
+
+    manager.rename_column(3, String::from("param")).unwrap();
+    assert_eq!(1, manager.rename_column(3, String::from("source")).unwrap_err().status());
+
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+
+    let resp = RefreshCatalogResponse::new(&restarted);
+    let col_names:Vec<(u32, String)> = resp.columns.into_iter().map(|(index, col)| (index, col.name)).collect();
+    assert_eq!(vec![(0, String::from("ts")), (1, String::from("source")), (3, String::from("param"))], col_names);
+    assert_eq!(None, restarted.catalog.column_index(&String::from("pattern_id")));
+    assert_eq!(Some(3), restarted.catalog.column_index(&String::from("param")));
+}
+
+#[test]
+fn it_loads_catalog_stored_before_versioning() {
+    use catalog::Column;
+
+    let db_home = "/tmp/hyena_test_unversioned_catalog";
+    fs::remove_dir_all(db_home).ok();
+    fs::create_dir_all(db_home).unwrap();
+
+    // Columns and partitions (min_ts, max_ts, id, location) were the only fields
+    let columns = vec![
+        Column { data_type: BlockType::Int64Dense, name: String::from("ts") },
+        Column { data_type: BlockType::String, name: String::from("p1") }
+    ];
+    let partitions = vec![(1000u64, 2000u64, 123u64, format!("{}/partitions/1000", db_home))];
+    save_data(&format!("{}/catalog.bin", db_home), &(columns.to_owned(), partitions)).unwrap();
+
+    let mut manager = Manager::new(String::from(db_home));
+    manager.reload_catalog().unwrap();
+    assert_eq!(columns, manager.catalog.columns);
+    assert_eq!(Vec::<u32>::new(), manager.catalog.dropped_columns);
+
+    let part_info = manager.find_partition_info(123).unwrap();
+    assert_eq!((1000, 2000), (part_info.min_ts, part_info.max_ts));
+    assert_eq!(None, part_info.row_count());
+
+    // Stored again in the current layout
+    manager.drop_column(1).unwrap();
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+    assert_eq!(manager.catalog, restarted.catalog);
+
+    File::create(manager.catalog_path()).unwrap().write_all(&[1, 2, 3]).unwrap();
+    assert_eq!(6, restarted.reload_catalog().unwrap_err().status());
+}
+
+#[test]
+fn it_widens_column_type() {
+    use api::{create_test_manager, part_scan_and_materialize, ScanRequest};
+
+    let db_home = "/tmp/hyena_test_alter_column_type";
+    let mut manager = create_test_manager(db_home);
+    let part_info = manager.catalog.available_partitions[0].to_owned();
+
+    // pattern_id is not set in all rows
+    assert_eq!(1, manager.alter_column_type(2, BlockType::Int64Dense).unwrap_err().status());
+    assert_eq!(3, manager.alter_column_type(2, BlockType::Int16Sparse).unwrap_err().status());
+    assert_eq!(BlockType::Int32Sparse, manager.catalog.columns[2].data_type);
+    assert!(!Path::new(&format!("{}/block_2.bin.tmp", part_info.location)).exists());
+
+    let req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: None,
+        filters: vec![ScanFilter { column: 2, op: ScanComparison::Eq, val: 5, str_val: vec![], vals: vec![], str_vals: vec![] }],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![0, 2]
+    };
+    let before = part_scan_and_materialize(&manager, &req).unwrap();
+
+    manager.alter_column_type(2, BlockType::Int64Sparse).unwrap();
+    assert_eq!(Block::Int64Sparse(Int64SparseBlock { data: vec![(0, 5), (2, 5), (3, 7)] }), manager.load_block(&part_info, 2).unwrap());
+
+    let after = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(before.row_count, after.row_count);
+    assert_eq!(before.blocks[0], after.blocks[0]);
+    assert_eq!(vec![(0, BlockType::Int64Dense), (2, BlockType::Int64Sparse)], after.col_types);
+
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+    assert_eq!(BlockType::Int64Sparse, restarted.catalog.columns[2].data_type);
+}
+
+#[test]
+fn it_keeps_catalog_when_type_change_fails() {
+    use api::create_test_manager;
+
+    let db_home = "/tmp/hyena_test_alter_column_type_failed";
+    let mut manager = create_test_manager(db_home);
+    let part_info = manager.catalog.available_partitions[0].to_owned();
+
+    // Catalog can't be written
+    let tmp_catalog_path = format!("{}.tmp", manager.catalog_path());
+    fs::create_dir(&tmp_catalog_path).unwrap();
+    assert_eq!(6, manager.alter_column_type(2, BlockType::Int64Sparse).unwrap_err().status());
+    assert_eq!(BlockType::Int32Sparse, manager.catalog.columns[2].data_type);
+    assert!(!Path::new(&format!("{}/block_2.bin.tmp", part_info.location)).exists());
+
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+    assert_eq!(BlockType::Int32Sparse, restarted.catalog.columns[2].data_type);
+
+    fs::remove_dir(&tmp_catalog_path).unwrap();
+    manager.alter_column_type(2, BlockType::Int64Sparse).unwrap();
+    assert!(!Path::new(&tmp_catalog_path).exists());
+
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+    assert_eq!(BlockType::Int64Sparse, restarted.catalog.columns[2].data_type);
+}
+
+#[test]
+fn it_finishes_column_type_change_on_start() {
+    use api::create_test_manager;
+
+    let db_home = "/tmp/hyena_test_alter_column_type_restart";
+    let mut manager = create_test_manager(db_home);
+    let part_info = manager.catalog.available_partitions[0].to_owned();
+    let block_path = |col_index| format!("{}/block_{}.bin", part_info.location, col_index);
+
+    let narrow_block = read_block(&block_path(2)).unwrap();
+    manager.alter_column_type(2, BlockType::Int64Sparse).unwrap();
+
+    // Died after the catalog was stored, but before the blocks were renamed
+    fs::rename(block_path(2), format!("{}.tmp", block_path(2))).unwrap();
+    save_data(&block_path(2), &narrow_block).unwrap();
+    // Died before a change of column 3 was committed
+    save_data(&format!("{}.tmp", block_path(3)), &narrow_block).unwrap();
+
+    let mut restarted = Manager::new(String::from(db_home));
+    restarted.reload_catalog().unwrap();
+
+    assert_eq!(BlockType::Int64Sparse, read_block(&block_path(2)).unwrap().data_type());
+    assert!(!Path::new(&format!("{}.tmp", block_path(2))).exists());
+    assert_eq!(BlockType::String, read_block(&block_path(3)).unwrap().data_type());
+    assert!(!Path::new(&format!("{}.tmp", block_path(3))).exists());
+
+    // Stored block can't be converted
+    save_data(&block_path(2), &Block::StringBlock(StringBlock::new())).unwrap();
+    assert_eq!(6, restarted.load_block(&part_info, 2).unwrap_err().status());
+}
+
+#[test]
+fn it_dictionary_encodes_string_column() {
+    use api::{create_test_manager, part_scan_and_materialize, ScanRequest};
+
+    let mut manager = create_test_manager("/tmp/hyena_test_dict_string");
+    let part_info = manager.catalog.available_partitions[0].to_owned();
+
+    let mut req = ScanRequest {
+        min_ts: 0,
+        max_ts: u64::max_value(),
+        partition_id: None,
+        filters: vec![ScanFilter { column: 3, op: ScanComparison::Eq, val: 0, str_val: "y".as_bytes().to_vec(), vals: vec![], str_vals: vec![] }],
+        filter_expression: None,
+        offset: 0,
+        limit: None,
+        order_by: None,
+        projection: vec![0, 3]
+    };
+    let before = part_scan_and_materialize(&manager, &req).unwrap();
+
+    manager.alter_column_type(3, BlockType::DictString).unwrap();
+    match manager.load_block(&part_info, 3).unwrap() {
+        Block::DictString(ref b) => assert_eq!(vec!["x".as_bytes().to_vec(), "y".as_bytes().to_vec(), "z".as_bytes().to_vec()], b.dict),
+        other => panic!("Unexpected block {:?}", other)
+    }
+
+    let after = part_scan_and_materialize(&manager, &req).unwrap();
+    assert_eq!(2, after.row_count);
+    assert_eq!(before.blocks[0], after.blocks[0]);
+
+    req.filters[0].op = ScanComparison::NotEq;
+    assert_eq!(4, part_scan_and_materialize(&manager, &req).unwrap().row_count);
+}
@@ -2,17 +2,21 @@ use bincode::{serialize, deserialize, Infinite};
 
 use nanomsg::{Socket, Protocol, Error};
 
-use api::{ApiMessage, ApiOperation, part_scan_and_materialize, handle_data_compaction, GenericResponse, DataCompactionRequest};
+use api::{ApiMessage, ApiOperation, ApiResponse, RefreshCatalogResponse, part_scan_and_materialize, part_scan_and_aggregate, part_scan_and_group, part_scan_and_histogram, handle_data_compaction, DataCompactionRequest};
+use error;
 use manager::Manager;
+use cursor::CursorRegistry;
 
 use std::io::{Read, Write};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::fs::{Permissions, metadata, set_permissions};
 use std::os::unix::fs::PermissionsExt;
 
 const FLUSH_AFTER_ROWS: usize = 10_000;
 const FLUSH_AFTER_SECS: usize = 300;
+const CURSOR_EXPIRE_AFTER_SECS: u64 = 300;
+const MAX_OPEN_CURSORS: usize = 64;
 const HYENA_SOCKET_PATH: &str = "/tmp/hyena.ipc";
 
 pub fn start_endpoint(manager : &mut Manager) {
@@ -23,63 +27,36 @@ pub fn start_endpoint(manager : &mut Manager) {
     set_permissions(HYENA_SOCKET_PATH, perms).unwrap();
     let mut last_flush = None::<Instant>;
     let mut rows_inserted = 0_usize;
+    let mut cursors = CursorRegistry::new(Duration::from_secs(CURSOR_EXPIRE_AFTER_SECS), MAX_OPEN_CURSORS);
 
     while true {
         println!("Waiting for message...");
 
         let mut buf: Vec<u8> = Vec::new();
-        socket.read_to_end(&mut buf).unwrap();
+        if let Err(e) = socket.read_to_end(&mut buf) {
+            println!("Unable to read message: {}", e);
+            continue;
+        }
 
 //        println!("Received buffer: {:?}", buf);
 
-        let req : ApiMessage = deserialize(&buf[..]).unwrap();
+        // Whatever goes wrong, the client gets an error response and we keep on serving others
+        let response = deserialize::<ApiMessage>(&buf[..])
+            .map_err(error::Error::from)
+            .and_then(|req| handle_request(manager, &mut cursors, &req, &mut rows_inserted));
 
-        match req.op_type {
-            ApiOperation::Scan => {
-                println!("Scan request: {:?}", req.extract_scan_request());
+        if let Err(ref e) = response {
+            println!("Request failed: {}", e);
+        }
 
-                let scan_request = req.extract_scan_request();
-                let materialized_msg = part_scan_and_materialize(manager, &scan_request);
-                let buf = serialize(&materialized_msg, Infinite).unwrap();
-                socket.write(&buf).unwrap();
-            },
-            ApiOperation::RefreshCatalog => {
-                println!("Refresh catalog response");
+        if let Err(e) = socket.write(&ApiResponse::create_as_buf(response)) {
+            println!("Unable to send response: {}", e);
+        }
 
-                let buf = serialize(&manager.catalog, Infinite).unwrap();
-                socket.write(&buf).unwrap();
-            }
-            ApiOperation::Insert => {
-                println!("Insert request");
-                let materialized_msg = &req.extract_insert_message();
-                manager.insert(&materialized_msg);
-
-                rows_inserted += materialized_msg.row_count as usize;
-
-                socket.write(&GenericResponse::create_as_buf(0));
-            },
-            ApiOperation::AddColumn => {
-                println!("Add column request");
-                let materialized_msg = &req.extract_add_column_message();
-                manager.add_column(materialized_msg.column_type.to_owned(), materialized_msg.column_name.to_owned());
-
-                socket.write(&GenericResponse::create_as_buf(0));
-            },
-            ApiOperation::Flush => {
-                println!("Flush request");
-                manager.dump_in_mem_partition();
-
-                socket.write(&GenericResponse::create_as_buf(0));
-            },
-            ApiOperation::DataCompaction => {
-                println!("Data compaction");
-
-                let compaction_msg = &req.extract_data_compaction_request();
-                handle_data_compaction(manager, compaction_msg);
-
-                socket.write(&GenericResponse::create_as_buf(0));
-            }
-            _ => println!("Not supported...")
+        cursors.expire();
+
+        if let Err(e) = manager.purge_dropped_blocks() {
+            println!("Removing blocks of dropped columns failed: {}", e);
         }
 
         // check if we need to flush
@@ -89,7 +66,122 @@ pub fn start_endpoint(manager : &mut Manager) {
             last_flush = Some(Instant::now());
             rows_inserted = 0;
             println!("Forced flush");
-            manager.dump_in_mem_partition();
+            if let Err(e) = manager.dump_in_mem_partition() {
+                println!("Forced flush failed: {}", e);
+            }
+        }
+    }
+}
+
+// Returns the serialized response payload
+fn handle_request(manager : &mut Manager, cursors : &mut CursorRegistry, req : &ApiMessage, rows_inserted : &mut usize) -> error::Result<Vec<u8>> {
+    match req.op_type {
+        ApiOperation::Scan => {
+            let scan_request = req.extract_scan_request()?;
+            println!("Scan request: {:?}", scan_request);
+
+            let materialized_msg = part_scan_and_materialize(manager, &scan_request)?;
+            Ok(serialize(&materialized_msg, Infinite)?)
+        },
+        ApiOperation::ScanCursor => {
+            let cursor_scan_request = req.extract_cursor_scan_request()?;
+            println!("Cursor scan request: {:?}", cursor_scan_request);
+
+            let cursor_response = cursors.open(manager, &cursor_scan_request)?;
+            Ok(serialize(&cursor_response, Infinite)?)
+        },
+        ApiOperation::FetchMore => {
+            let fetch_more_request = req.extract_fetch_more_request()?;
+            println!("Fetch more request: {:?}", fetch_more_request);
+
+            let cursor_response = cursors.fetch_more(manager, fetch_more_request.cursor_id)?;
+            Ok(serialize(&cursor_response, Infinite)?)
+        },
+        ApiOperation::Aggregate => {
+            let aggregate_request = req.extract_aggregate_request()?;
+            println!("Aggregate request: {:?}", aggregate_request);
+
+            let aggregate_response = part_scan_and_aggregate(manager, &aggregate_request)?;
+            Ok(serialize(&aggregate_response, Infinite)?)
+        },
+        ApiOperation::GroupBy => {
+            let group_by_request = req.extract_group_by_request()?;
+            println!("Group by request: {:?}", group_by_request);
+
+            let group_by_response = part_scan_and_group(manager, &group_by_request)?;
+            Ok(serialize(&group_by_response, Infinite)?)
+        },
+        ApiOperation::Histogram => {
+            let histogram_request = req.extract_histogram_request()?;
+            println!("Histogram request: {:?}", histogram_request);
+
+            let histogram_response = part_scan_and_histogram(manager, &histogram_request)?;
+            Ok(serialize(&histogram_response, Infinite)?)
+        },
+        ApiOperation::RefreshCatalog => {
+            println!("Refresh catalog response");
+
+            Ok(serialize(&RefreshCatalogResponse::new(manager), Infinite)?)
+        }
+        ApiOperation::Insert => {
+            println!("Insert request");
+            let materialized_msg = &req.extract_insert_message()?;
+            manager.insert(&materialized_msg)?;
+
+            *rows_inserted += materialized_msg.row_count as usize;
+
+            Ok(Vec::new())
+        },
+        ApiOperation::InsertNamed => {
+            println!("Named insert request");
+            let named_insert_msg = &req.extract_named_insert_message()?;
+            let named_insert_response = manager.insert_named(named_insert_msg)?;
+
+            *rows_inserted += named_insert_msg.row_count as usize;
+
+            Ok(serialize(&named_insert_response, Infinite)?)
+        },
+        ApiOperation::AddColumn => {
+            println!("Add column request");
+            let materialized_msg = &req.extract_add_column_message()?;
+            manager.add_column(materialized_msg.column_type.to_owned(), materialized_msg.column_name.to_owned())?;
+
+            Ok(Vec::new())
+        },
+        ApiOperation::DropColumn => {
+            let drop_column_request = req.extract_drop_column_request()?;
+            println!("Drop column request: {:?}", drop_column_request);
+            manager.drop_column(drop_column_request.column)?;
+
+            Ok(Vec::new())
+        },
+        ApiOperation::RenameColumn => {
+            let rename_column_request = req.extract_rename_column_request()?;
+            println!("Rename column request: {:?}", rename_column_request);
+            manager.rename_column(rename_column_request.column, rename_column_request.new_name)?;
+
+            Ok(Vec::new())
+        },
+        ApiOperation::AlterColumnType => {
+            let alter_column_type_request = req.extract_alter_column_type_request()?;
+            println!("Alter column type request: {:?}", alter_column_type_request);
+            manager.alter_column_type(alter_column_type_request.column, alter_column_type_request.data_type)?;
+
+            Ok(Vec::new())
+        },
+        ApiOperation::Flush => {
+            println!("Flush request");
+            manager.dump_in_mem_partition()?;
+
+            Ok(Vec::new())
+        },
+        ApiOperation::DataCompaction => {
+            println!("Data compaction");
+
+            let compaction_msg = &req.extract_data_compaction_request()?;
+            handle_data_compaction(manager, compaction_msg)?;
+
+            Ok(Vec::new())
         }
     }
-}
\ No newline at end of file
+}
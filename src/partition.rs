@@ -13,7 +13,109 @@ pub struct PartitionMetadata {
     pub min_ts : u64,
     pub max_ts : u64,
     pub id : u64,
-    pub existing_blocks: Vec<u32>
+    pub existing_blocks: Vec<u32>,
+    // Zone maps of the existing blocks
+    pub block_stats: Vec<(u32, BlockStats)>
+}
+
+// Block value, in the same representation as ScanFilter values are compared with
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum StatValue {
+    UInt(u64),
+    Int(i64),
+    Float(f64),
+    Str(Vec<u8>)
+}
+
+// Lets scans skip a block without loading it, when no row (or every row) can match the filter
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct BlockStats {
+    pub non_null_count : u32,
+    // Min and max value; not known for bitmaps or when there's a NaN
+    pub bounds : Option<(StatValue, StatValue)>
+}
+
+fn value_bounds<T : PartialOrd + Clone, I : Iterator<Item=T>>(values : I) -> Option<(T, T)> {
+    let mut bounds:Option<(T, T)> = None;
+
+    for v in values {
+        // NaN can't be ordered
+        if v.partial_cmp(&v).is_none() {
+            return None;
+        }
+
+        bounds = match bounds {
+            None => Some((v.clone(), v)),
+            Some((min, max)) => Some((if v < min { v.clone() } else { min }, if v > max { v } else { max }))
+        };
+    }
+
+    bounds
+}
+
+// Strings are cut to this length in the zone maps, as they are copied to the catalog
+const MAX_STAT_STR_LEN : usize = 32;
+
+// Prefix of the value, which is not above it
+fn str_lower_bound(v : &[u8]) -> Vec<u8> {
+    v[..cmp::min(v.len(), MAX_STAT_STR_LEN)].to_vec()
+}
+
+// Shortest string above every string starting with the prefix of the value, None when its prefix is all 0xFF
+fn str_upper_bound(v : &[u8]) -> Option<Vec<u8>> {
+    if v.len() <= MAX_STAT_STR_LEN {
+        return Some(v.to_vec());
+    }
+
+    let mut bound = v[..MAX_STAT_STR_LEN].to_vec();
+    while let Some(last) = bound.pop() {
+        if last < 0xFF {
+            bound.push(last + 1);
+            return Some(bound);
+        }
+    }
+
+    None
+}
+
+impl BlockStats {
+    pub fn of(block : &Block) -> BlockStats {
+        let uint = |bounds : Option<(u64, u64)>| bounds.map(|(min, max)| (StatValue::UInt(min), StatValue::UInt(max)));
+        let int = |bounds : Option<(i64, i64)>| bounds.map(|(min, max)| (StatValue::Int(min), StatValue::Int(max)));
+        let float = |bounds : Option<(f64, f64)>| bounds.map(|(min, max)| (StatValue::Float(min), StatValue::Float(max)));
+        // Bounds of cut strings still enclose all the values, so the zone maps stay correct (just less precise)
+        let str = |bounds : Option<(&[u8], &[u8])>| bounds.and_then(|(min, max)| {
+            str_upper_bound(max).map(|max| (StatValue::Str(str_lower_bound(min)), StatValue::Str(max)))
+        });
+
+        let bounds = match block {
+            &Block::Int64Dense(ref b) => uint(value_bounds(b.data.iter().cloned())),
+            &Block::Int64Sparse(ref b) => uint(value_bounds(b.data.iter().map(|pair| pair.1))),
+            &Block::Int32Sparse(ref b) => uint(value_bounds(b.data.iter().map(|pair| pair.1 as u64))),
+            &Block::Int16Sparse(ref b) => uint(value_bounds(b.data.iter().map(|pair| pair.1 as u64))),
+            &Block::Int8Sparse(ref b) => uint(value_bounds(b.data.iter().map(|pair| pair.1 as u64))),
+            &Block::SignedInt64Sparse(ref b) => int(value_bounds(b.data.iter().map(|pair| pair.1))),
+            &Block::SignedInt32Sparse(ref b) => int(value_bounds(b.data.iter().map(|pair| pair.1 as i64))),
+            &Block::SignedInt16Sparse(ref b) => int(value_bounds(b.data.iter().map(|pair| pair.1 as i64))),
+            &Block::SignedInt8Sparse(ref b) => int(value_bounds(b.data.iter().map(|pair| pair.1 as i64))),
+            &Block::Float64Dense(ref b) => float(value_bounds(b.data.iter().cloned())),
+            &Block::Float64Sparse(ref b) => float(value_bounds(b.data.iter().map(|pair| pair.1))),
+            &Block::Float32Sparse(ref b) => float(value_bounds(b.data.iter().map(|pair| pair.1 as f64))),
+            &Block::StringBlock(ref b) => {
+                let mut values:Vec<&[u8]> = Vec::with_capacity(b.index_data.len());
+                b.for_each_value(|_, v| values.push(v));
+                str(value_bounds(values.into_iter()))
+            },
+            &Block::DictString(ref b) => str(value_bounds(b.codes.data.iter().map(|pair| b.value(pair.1)))),
+            // Rows not set are false, which can't be told without the row count
+            &Block::Bitmap(_) => None
+        };
+
+        BlockStats {
+            non_null_count: block.len() as u32,
+            bounds: bounds
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -30,7 +132,8 @@ impl Partition {
                 min_ts: 0,
                 max_ts: 0,
                 id: 0,
-                existing_blocks: Vec::new()
+                existing_blocks: Vec::new(),
+                block_stats: Vec::new()
             },
             blocks: Vec::new()
         }
@@ -63,10 +166,12 @@ impl Partition {
         }
 
         self.metadata.existing_blocks = Vec::new();
+        self.metadata.block_stats = Vec::new();
         
         for (i, block) in self.blocks.iter().enumerate() {
             if block.len() > 0 {
                 self.metadata.existing_blocks.push(i as u32);
+                self.metadata.block_stats.push((i as u32, BlockStats::of(block)));
             }
         }
     }
@@ -2,6 +2,7 @@ use catalog::PartitionInfo;
 use api::{ScanResultMessage, ScanFilter, ScanComparison};
 use catalog::Catalog;
 use manager::{Manager, BlockCache};
+use error;
 
 
 
@@ -16,6 +17,11 @@ impl BlockScanConsumer {
         BlockScanConsumer { matching_offsets: Vec::new() }
     }
 
+    // Offsets are ascending unless the consumer is used for reordering
+    pub fn is_ascending(&self) -> bool {
+        self.matching_offsets.windows(2).all(|w| w[0] < w[1])
+    }
+
     pub fn merge_or_scans(scans : &Vec<BlockScanConsumer>) -> BlockScanConsumer {
         let mut new_matching_offsets : Vec<u32> = Vec::new();
 
@@ -126,7 +132,25 @@ impl BlockScanConsumer {
         BlockScanConsumer { matching_offsets: new_matching_offsets }
     }
 
-    pub fn materialize(&self, manager : &Manager, block_cache: &mut BlockCache, projection : &Vec<u32>, msg : &mut ScanResultMessage) {
+    // All offsets in [0, row_count) which are not matched by this consumer
+    pub fn complement(&self, row_count : u32) -> BlockScanConsumer {
+        let mut new_matching_offsets : Vec<u32> = Vec::new();
+        let mut index = 0 as usize;
+
+        for offset in 0..row_count {
+            while index < self.matching_offsets.len() && self.matching_offsets[index] < offset {
+                index += 1;
+            }
+
+            if index == self.matching_offsets.len() || self.matching_offsets[index] != offset {
+                new_matching_offsets.push(offset);
+            }
+        }
+
+        BlockScanConsumer { matching_offsets: new_matching_offsets }
+    }
+
+    pub fn materialize(&self, manager : &Manager, block_cache: &mut BlockCache, projection : &Vec<u32>, msg : &mut ScanResultMessage) -> error::Result<()> {
         // This should work only on empty message (different implementation is of course possible,
         // if you think it would make sense to merge results)
         assert_eq!(msg.row_count, 0);
@@ -142,7 +166,7 @@ impl BlockScanConsumer {
             let block_maybe = block_cache.cached_block_maybe(*col_index);
             match block_maybe {
                 None => {
-                    let block = manager.load_block(&block_cache.partition_info, *col_index);
+                    let block = manager.load_block(&block_cache.partition_info, *col_index)?;
                     msg.blocks.push(block.consume(self));
                 },
                 Some(ref x) => {
@@ -151,6 +175,8 @@ impl BlockScanConsumer {
             };
 
         }
+
+        Ok(())
     }
 
 }
@@ -206,4 +232,25 @@ fn it_merges_consumers() {
         BlockScanConsumer::merge_and_scans(&consumers2)
     );
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn it_complements_consumer() {
+    let consumer = BlockScanConsumer {
+        matching_offsets: vec![0,3,4,7]
+    };
+
+    assert_eq!(
+        BlockScanConsumer{
+            matching_offsets: vec![1,2,5,6]
+        },
+        consumer.complement(8)
+    );
+
+    assert_eq!(
+        BlockScanConsumer{
+            matching_offsets: vec![0,1,2]
+        },
+        BlockScanConsumer::new().complement(3)
+    );
+}